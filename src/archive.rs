@@ -0,0 +1,234 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use zip::ZipArchive;
+
+use crate::{
+    decompress::decompress, matcher::Matcher, search::search_decoded, sink::Sink, walk::Filters,
+    Config, READ_BUFFER_SIZE,
+};
+
+/// File name endings of tar and zip archives, including compressed tar archives.
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.zst", ".tzst", ".zip",
+];
+
+/// Returns whether the name of the file at `path` ends like the name of an archive.
+///
+/// Archives are recognised by their contents when they are searched; the name only
+/// tells the walker which files to keep even though globs or file types exclude them.
+pub(crate) fn has_archive_name(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    ARCHIVE_EXTENSIONS
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+/// Returns whether `head`, the start of a file, is the start of a zip archive.
+pub(crate) fn is_zip(head: &[u8]) -> bool {
+    // An empty archive holds only its end of central directory record.
+    head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06")
+}
+
+/// Returns whether `head`, the start of a file, is the start of a tar archive.
+///
+/// POSIX and GNU tar archives have `ustar` at offset 257 of their first header.
+pub(crate) fn is_tar(head: &[u8]) -> bool {
+    head.get(257..262) == Some(b"ustar")
+}
+
+/// Searches each regular file of the tar archive read from `reader`, in archive order.
+///
+/// Returns the total number of selected lines. Members are reported to `sink` as
+/// `archive:member`, where `archive` is the path of the archive itself.
+pub(crate) fn search_tar<R: Read, S: Sink + ?Sized>(
+    reader: R,
+    sink: &mut S,
+    archive: &Path,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+) -> io::Result<u64> {
+    let mut count = 0;
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = entry.path()?.into_owned();
+        count += search_member(entry, sink, archive, &member, matcher, filters, config)?;
+        if count > 0 && config.quiet {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// Searches each regular file of the zip archive read from `reader`, in archive order.
+///
+/// Returns the total number of selected lines. Members are reported to `sink` as
+/// `archive:member`, where `archive` is the path of the archive itself.
+pub(crate) fn search_zip<R: Read + Seek, S: Sink + ?Sized>(
+    reader: R,
+    sink: &mut S,
+    archive: &Path,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+) -> io::Result<u64> {
+    let mut zip = ZipArchive::new(reader)?;
+    let mut count = 0;
+    for index in 0..zip.len() {
+        let file = zip.by_index(index)?;
+        if !file.is_file() {
+            continue;
+        }
+        let member = PathBuf::from(file.name());
+        count += search_member(file, sink, archive, &member, matcher, filters, config)?;
+        if count > 0 && config.quiet {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// Searches one member of an archive with [`search_decoded`], unless the globs or file
+/// types exclude it. With `config.search_zip`, a compressed member is decompressed first.
+fn search_member<R: Read, S: Sink + ?Sized>(
+    reader: R,
+    sink: &mut S,
+    archive: &Path,
+    member: &Path,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+) -> io::Result<u64> {
+    let member = member.strip_prefix("./").unwrap_or(member);
+    if filters.excludes_member(member) {
+        return Ok(0);
+    }
+    let mut path = archive.as_os_str().to_owned();
+    path.push(":");
+    path.push(member);
+    let path = PathBuf::from(path);
+    let reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+    if config.search_zip {
+        search_decoded(decompress(reader)?, sink, &path, matcher, config)
+    } else {
+        search_decoded(reader, sink, &path, matcher, config)
+    }
+}
+
+/// Reads the rest of `reader` into memory, so a zip archive that cannot be seeked, such
+/// as one read from standard input or decompressed on the fly, can be searched.
+pub(crate) fn buffer_zip<R: BufRead>(mut reader: R) -> io::Result<io::Cursor<Vec<u8>>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(io::Cursor::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+    use crate::Collector;
+
+    fn config() -> Config {
+        Config {
+            query: "nobody".into(),
+            search_archives: true,
+            ..Config::default()
+        }
+    }
+
+    fn tar(members: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(members: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_directory("src/", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        for (path, contents) in members {
+            writer
+                .start_file(*path, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn matched_paths(collector: &Collector) -> Vec<(&str, usize)> {
+        collector
+            .lines
+            .iter()
+            .map(|line| (line.path.to_str().unwrap(), line.line_number))
+            .collect()
+    }
+
+    #[test]
+    fn searches_tar_members() {
+        let data = tar(&[("./src/a.rs", "nobody\n"), ("notes.txt", "x\nnobody\n")]);
+        assert!(is_tar(&data) && !is_zip(&data));
+        let config = config();
+        let matcher = Matcher::new(&config).unwrap();
+        let filters = Filters::new(&config).unwrap();
+        let mut collector = Collector::new();
+
+        let count = search_tar(
+            &data[..],
+            &mut collector,
+            Path::new("a.tar"),
+            &matcher,
+            &filters,
+            &config,
+        );
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(
+            matched_paths(&collector),
+            vec![("a.tar:src/a.rs", 1), ("a.tar:notes.txt", 2)]
+        );
+    }
+
+    #[test]
+    fn searches_zip_members_chosen_by_globs() {
+        let data = zip(&[("src/a.rs", "nobody\n"), ("src/b.js", "nobody\n")]);
+        assert!(is_zip(&data) && !is_tar(&data));
+        let config = Config {
+            globs: vec!["*.rs".into()],
+            ..config()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let filters = Filters::new(&config).unwrap();
+        let mut collector = Collector::new();
+
+        let reader = Cursor::new(data);
+        let count = search_zip(
+            reader,
+            &mut collector,
+            Path::new("a.zip"),
+            &matcher,
+            &filters,
+            &config,
+        );
+        assert_eq!(count.unwrap(), 1);
+        assert_eq!(matched_paths(&collector), vec![("a.zip:src/a.rs", 1)]);
+        assert_eq!(collector.files.len(), 1);
+    }
+}
@@ -0,0 +1,259 @@
+use std::ops::Range;
+
+/// Marks an offset in a folded line that falls inside the folding of one character.
+const INSIDE: usize = usize::MAX;
+
+/// A line with its case folded, so that two strings that differ only in case fold to the
+/// same bytes, and the offsets in the line each folded character came from.
+///
+/// Folding is Unicode full case folding, as in the `C` and `F` entries of Unicode's
+/// CaseFolding.txt: besides lowercasing, `ß` folds to `ss`, the final sigma `ς` to `σ`,
+/// and `İ` to `i` followed by a combining dot. The Turkic mappings are not used, so the
+/// dotless `ı` only matches itself. Bytes that are not valid UTF-8 are kept as they are.
+pub(crate) struct FoldedLine {
+    pub(crate) text: Vec<u8>,
+    /// For each offset in `text`, and the end, the matching offset in the line, or
+    /// `INSIDE`. Empty when both are the same, as with ASCII lines.
+    origins: Vec<usize>,
+}
+
+impl FoldedLine {
+    /// Folds `line`. With `ascii_only`, only the letters `A` to `Z` are folded, which
+    /// keeps every offset where it was and is much faster.
+    pub(crate) fn new(line: &[u8], ascii_only: bool) -> FoldedLine {
+        if ascii_only || line.is_ascii() {
+            return FoldedLine {
+                text: line.to_ascii_lowercase(),
+                origins: Vec::new(),
+            };
+        }
+        let mut text = Vec::with_capacity(line.len());
+        let mut origins = Vec::with_capacity(line.len() + 1);
+        let mut chunk_start = 0;
+        for chunk in line.utf8_chunks() {
+            let valid = chunk.valid();
+            for (index, c) in valid.char_indices() {
+                match fold(c) {
+                    Fold::Char(folded) => {
+                        text.extend_from_slice(folded.encode_utf8(&mut [0; 4]).as_bytes())
+                    }
+                    Fold::Str(folded) => text.extend_from_slice(folded.as_bytes()),
+                }
+                origins.push(chunk_start + index);
+                origins.resize(text.len(), INSIDE);
+            }
+            let invalid_start = chunk_start + valid.len();
+            text.extend_from_slice(chunk.invalid());
+            origins.extend(invalid_start..invalid_start + chunk.invalid().len());
+            chunk_start = invalid_start + chunk.invalid().len();
+        }
+        origins.push(line.len());
+        FoldedLine { text, origins }
+    }
+
+    /// Maps a range of `text` back to the line, or returns `None` if it starts or ends
+    /// partway through the folding of one character, as `s` does in the `ss` of `ß`.
+    pub(crate) fn original_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        if self.origins.is_empty() {
+            return Some(range);
+        }
+        let (start, end) = (self.origins[range.start], self.origins[range.end]);
+        (start != INSIDE && end != INSIDE).then_some(start..end)
+    }
+}
+
+/// The case folding of one character.
+enum Fold {
+    Char(char),
+    Str(&'static str),
+}
+
+/// Returns the full case folding of `c`.
+///
+/// Characters that fold to one character mostly fold to their lowercase form, so only
+/// the exceptions are listed in tables. Cherokee letters fold to uppercase, but since
+/// lowercasing also maps each pair of letters to one of them, it is used for them too.
+fn fold(c: char) -> Fold {
+    if c.is_ascii() {
+        return Fold::Char(c.to_ascii_lowercase());
+    }
+    if let Ok(index) = FULL_FOLDING.binary_search_by_key(&c, |&(from, _)| from) {
+        return Fold::Str(FULL_FOLDING[index].1);
+    }
+    if let Ok(index) = SIMPLE_FOLDING.binary_search_by_key(&c, |&(from, _)| from) {
+        return Fold::Char(SIMPLE_FOLDING[index].1);
+    }
+    let mut lowercase = c.to_lowercase();
+    match (lowercase.next(), lowercase.next()) {
+        (Some(lower), None) => Fold::Char(lower),
+        // Only `İ` lowercases to more than one character, and it is in `FULL_FOLDING`.
+        _ => Fold::Char(c),
+    }
+}
+
+/// Characters that fold to more than one character, from the `F` entries of
+/// CaseFolding.txt, sorted by character.
+const FULL_FOLDING: &[(char, &str)] = &[
+    ('\u{df}', "ss"),
+    ('\u{130}', "i\u{307}"),
+    ('\u{149}', "\u{2bc}n"),
+    ('\u{1f0}', "j\u{30c}"),
+    ('\u{390}', "\u{3b9}\u{308}\u{301}"),
+    ('\u{3b0}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{587}', "\u{565}\u{582}"),
+    ('\u{1e96}', "h\u{331}"),
+    ('\u{1e97}', "t\u{308}"),
+    ('\u{1e98}', "w\u{30a}"),
+    ('\u{1e99}', "y\u{30a}"),
+    ('\u{1e9a}', "a\u{2be}"),
+    ('\u{1e9e}', "ss"),
+    ('\u{1f50}', "\u{3c5}\u{313}"),
+    ('\u{1f52}', "\u{3c5}\u{313}\u{300}"),
+    ('\u{1f54}', "\u{3c5}\u{313}\u{301}"),
+    ('\u{1f56}', "\u{3c5}\u{313}\u{342}"),
+    ('\u{1f80}', "\u{1f00}\u{3b9}"),
+    ('\u{1f81}', "\u{1f01}\u{3b9}"),
+    ('\u{1f82}', "\u{1f02}\u{3b9}"),
+    ('\u{1f83}', "\u{1f03}\u{3b9}"),
+    ('\u{1f84}', "\u{1f04}\u{3b9}"),
+    ('\u{1f85}', "\u{1f05}\u{3b9}"),
+    ('\u{1f86}', "\u{1f06}\u{3b9}"),
+    ('\u{1f87}', "\u{1f07}\u{3b9}"),
+    ('\u{1f88}', "\u{1f00}\u{3b9}"),
+    ('\u{1f89}', "\u{1f01}\u{3b9}"),
+    ('\u{1f8a}', "\u{1f02}\u{3b9}"),
+    ('\u{1f8b}', "\u{1f03}\u{3b9}"),
+    ('\u{1f8c}', "\u{1f04}\u{3b9}"),
+    ('\u{1f8d}', "\u{1f05}\u{3b9}"),
+    ('\u{1f8e}', "\u{1f06}\u{3b9}"),
+    ('\u{1f8f}', "\u{1f07}\u{3b9}"),
+    ('\u{1f90}', "\u{1f20}\u{3b9}"),
+    ('\u{1f91}', "\u{1f21}\u{3b9}"),
+    ('\u{1f92}', "\u{1f22}\u{3b9}"),
+    ('\u{1f93}', "\u{1f23}\u{3b9}"),
+    ('\u{1f94}', "\u{1f24}\u{3b9}"),
+    ('\u{1f95}', "\u{1f25}\u{3b9}"),
+    ('\u{1f96}', "\u{1f26}\u{3b9}"),
+    ('\u{1f97}', "\u{1f27}\u{3b9}"),
+    ('\u{1f98}', "\u{1f20}\u{3b9}"),
+    ('\u{1f99}', "\u{1f21}\u{3b9}"),
+    ('\u{1f9a}', "\u{1f22}\u{3b9}"),
+    ('\u{1f9b}', "\u{1f23}\u{3b9}"),
+    ('\u{1f9c}', "\u{1f24}\u{3b9}"),
+    ('\u{1f9d}', "\u{1f25}\u{3b9}"),
+    ('\u{1f9e}', "\u{1f26}\u{3b9}"),
+    ('\u{1f9f}', "\u{1f27}\u{3b9}"),
+    ('\u{1fa0}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa1}', "\u{1f61}\u{3b9}"),
+    ('\u{1fa2}', "\u{1f62}\u{3b9}"),
+    ('\u{1fa3}', "\u{1f63}\u{3b9}"),
+    ('\u{1fa4}', "\u{1f64}\u{3b9}"),
+    ('\u{1fa5}', "\u{1f65}\u{3b9}"),
+    ('\u{1fa6}', "\u{1f66}\u{3b9}"),
+    ('\u{1fa7}', "\u{1f67}\u{3b9}"),
+    ('\u{1fa8}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa9}', "\u{1f61}\u{3b9}"),
+    ('\u{1faa}', "\u{1f62}\u{3b9}"),
+    ('\u{1fab}', "\u{1f63}\u{3b9}"),
+    ('\u{1fac}', "\u{1f64}\u{3b9}"),
+    ('\u{1fad}', "\u{1f65}\u{3b9}"),
+    ('\u{1fae}', "\u{1f66}\u{3b9}"),
+    ('\u{1faf}', "\u{1f67}\u{3b9}"),
+    ('\u{1fb2}', "\u{1f70}\u{3b9}"),
+    ('\u{1fb3}', "\u{3b1}\u{3b9}"),
+    ('\u{1fb4}', "\u{3ac}\u{3b9}"),
+    ('\u{1fb6}', "\u{3b1}\u{342}"),
+    ('\u{1fb7}', "\u{3b1}\u{342}\u{3b9}"),
+    ('\u{1fbc}', "\u{3b1}\u{3b9}"),
+    ('\u{1fc2}', "\u{1f74}\u{3b9}"),
+    ('\u{1fc3}', "\u{3b7}\u{3b9}"),
+    ('\u{1fc4}', "\u{3ae}\u{3b9}"),
+    ('\u{1fc6}', "\u{3b7}\u{342}"),
+    ('\u{1fc7}', "\u{3b7}\u{342}\u{3b9}"),
+    ('\u{1fcc}', "\u{3b7}\u{3b9}"),
+    ('\u{1fd2}', "\u{3b9}\u{308}\u{300}"),
+    ('\u{1fd3}', "\u{3b9}\u{308}\u{301}"),
+    ('\u{1fd6}', "\u{3b9}\u{342}"),
+    ('\u{1fd7}', "\u{3b9}\u{308}\u{342}"),
+    ('\u{1fe2}', "\u{3c5}\u{308}\u{300}"),
+    ('\u{1fe3}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{1fe4}', "\u{3c1}\u{313}"),
+    ('\u{1fe6}', "\u{3c5}\u{342}"),
+    ('\u{1fe7}', "\u{3c5}\u{308}\u{342}"),
+    ('\u{1ff2}', "\u{1f7c}\u{3b9}"),
+    ('\u{1ff3}', "\u{3c9}\u{3b9}"),
+    ('\u{1ff4}', "\u{3ce}\u{3b9}"),
+    ('\u{1ff6}', "\u{3c9}\u{342}"),
+    ('\u{1ff7}', "\u{3c9}\u{342}\u{3b9}"),
+    ('\u{1ffc}', "\u{3c9}\u{3b9}"),
+    ('\u{fb00}', "ff"),
+    ('\u{fb01}', "fi"),
+    ('\u{fb02}', "fl"),
+    ('\u{fb03}', "ffi"),
+    ('\u{fb04}', "ffl"),
+    ('\u{fb05}', "st"),
+    ('\u{fb06}', "st"),
+    ('\u{fb13}', "\u{574}\u{576}"),
+    ('\u{fb14}', "\u{574}\u{565}"),
+    ('\u{fb15}', "\u{574}\u{56b}"),
+    ('\u{fb16}', "\u{57e}\u{576}"),
+    ('\u{fb17}', "\u{574}\u{56d}"),
+];
+
+/// Characters that fold to a character other than their lowercase form, sorted by
+/// character.
+const SIMPLE_FOLDING: &[(char, char)] = &[
+    ('\u{b5}', '\u{3bc}'),
+    ('\u{17f}', '\u{73}'),
+    ('\u{345}', '\u{3b9}'),
+    ('\u{3c2}', '\u{3c3}'),
+    ('\u{3d0}', '\u{3b2}'),
+    ('\u{3d1}', '\u{3b8}'),
+    ('\u{3d5}', '\u{3c6}'),
+    ('\u{3d6}', '\u{3c0}'),
+    ('\u{3f0}', '\u{3ba}'),
+    ('\u{3f1}', '\u{3c1}'),
+    ('\u{3f5}', '\u{3b5}'),
+    ('\u{1c80}', '\u{432}'),
+    ('\u{1c81}', '\u{434}'),
+    ('\u{1c82}', '\u{43e}'),
+    ('\u{1c83}', '\u{441}'),
+    ('\u{1c84}', '\u{442}'),
+    ('\u{1c85}', '\u{442}'),
+    ('\u{1c86}', '\u{44a}'),
+    ('\u{1c87}', '\u{463}'),
+    ('\u{1c88}', '\u{a64b}'),
+    ('\u{1e9b}', '\u{1e61}'),
+    ('\u{1fbe}', '\u{3b9}'),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folded(text: &str) -> String {
+        String::from_utf8(FoldedLine::new(text.as_bytes(), false).text).unwrap()
+    }
+
+    #[test]
+    fn folds_full_case() {
+        assert_eq!(folded("Straße STRASSE"), "strasse strasse");
+        assert_eq!(folded("ΣΟΦΟΣ σοφος σοφοσ"), "σοφοσ σοφοσ σοφοσ");
+        assert_eq!(folded("İı"), "i\u{307}ı");
+        assert_eq!(folded("ﬁle µ K"), "file μ k");
+        assert_eq!(
+            FoldedLine::new("ÉCOLE".as_bytes(), true).text,
+            "École".as_bytes()
+        );
+    }
+
+    #[test]
+    fn ranges_map_back_to_whole_characters() {
+        // `ß` folds to two characters and the invalid byte is kept.
+        let line = FoldedLine::new(b"a\xc3\x9f\xff\xc3\x89", false);
+        assert_eq!(line.text, b"ass\xff\xc3\xa9");
+        assert_eq!(line.original_range(1..3), Some(1..3));
+        assert_eq!(line.original_range(0..2), None);
+        assert_eq!(line.original_range(3..6), Some(3..6));
+    }
+}
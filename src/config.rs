@@ -0,0 +1,1179 @@
+use std::{env, error::Error, ffi::OsString, fmt};
+
+use encoding_rs::Encoding;
+
+/// Usage text printed for `--help`.
+pub const USAGE: &str = "\
+Usage: quewuigrep [OPTION]... <query> [<path>...]
+
+Search for <query> in each <path> and print the matching lines. When more
+than one <path> is given, or with -r, each line is prefixed with the path it
+came from.
+With no <path>, or when <path> is -, standard input is searched; with -r and
+no <path>, the current directory is searched.
+
+Options:
+  -i, --ignore-case         ignore case distinctions in the query and the lines,
+                            using Unicode case folding, so STRASSE matches Straße
+      --ascii-case          with -i, ignore the case of ASCII letters only, which is
+                            faster; regular expressions are not affected
+  -E, --regex               treat the query as a regular expression
+  -v, --invert-match        print the lines that do not match
+  -n, --line-number         prefix each printed line with its line number
+  -b, --byte-offset         prefix each printed line with the byte offset of its start
+      --column              prefix each printed line with the column of its first match
+  -A, --after-context=NUM   print NUM lines of context after each match
+  -B, --before-context=NUM  print NUM lines of context before each match
+  -C, --context=NUM         print NUM lines of context before and after each match
+      --color[=WHEN]        highlight matches, paths and line numbers; WHEN is
+                            auto (the default), always or never
+  -c, --count               print only the number of matching lines
+  -l, --files-with-matches  print only the paths of files with a matching line
+  -L, --files-without-match print only the paths of files without a matching line
+      --json                print results as JSON Lines: begin, match, context and end
+                            events for each file, then a summary
+  -q, --quiet, --silent     print nothing and stop at the first matching line
+  -r, --recursive           search every file below each <path> that is a directory,
+                            skipping hidden files and files ignored by .gitignore,
+                            .ignore and .git/info/exclude
+      --hidden              search hidden files and directories when recursing
+      --no-ignore           don't skip files matched by ignore files when recursing
+  -g, --glob=GLOB           when recursing, search only files matching GLOB, or skip
+                            them if GLOB starts with !; may be repeated
+      --iglob=GLOB          like --glob, but ignoring case
+  -t, --type=TYPE           when recursing, search only files of TYPE, such as rust
+  -T, --type-not=TYPE       when recursing, skip files of TYPE
+      --type-add=NAME:GLOB  define file type NAME as the files matching GLOB, or add
+                            GLOB to it if it exists
+  -z, --search-zip          search the contents of gzip, bzip2, xz and zstd compressed
+                            files instead of the compressed bytes
+      --search-archives     search each file inside tar and zip archives, including
+                            compressed tar archives, as ARCHIVE:MEMBER; globs and
+                            file types also choose the members to search
+      --encoding=ENC        read input as ENC, such as utf-16le, latin1 or shift_jis;
+                            auto (the default) reads UTF-8 unless a BOM says otherwise
+      --output-encoding=ENC write printed lines and paths as ENC instead of UTF-8
+      --raw-lines           print lines as the bytes read, instead of replacing bytes
+                            that are not valid UTF-8 with U+FFFD
+      --binary-files=TYPE   how to search files containing a NUL byte: report (the
+                            default) prints \"Binary file X matches\" instead of their
+                            lines, skip leaves them out and text searches them as text
+  -j, --threads=NUM         search up to NUM files at once; 0 uses one thread per
+                            CPU (default 1)
+      --sort=ORDER          print files in ORDER: path sorts them by path, and
+                            none (the default) prints each file once it is searched
+  -h, --help                print this help and exit
+  -V, --version             print version information and exit
+
+Short options can be combined, as in -inr. Use -- to stop option parsing,
+for example to search for a query that starts with a dash.
+
+Setting the CASE_INSENSITIVE environment variable has the same effect as -i.
+
+The exit status is 0 if a line was selected, 1 if no line was selected and
+2 if an error occurred. With -q, it is 0 as soon as a line is selected, even
+if an error occurred before. With -L, a line counts as selected when a path
+is printed.";
+
+/// Holds the configuration for the search.
+///
+/// # Fields
+///
+/// * `query` - The string to search for.
+/// * `paths` - The files, or with `recursive` the directories, to search in, which need not be valid
+///   UTF-8. `-` stands for standard input, which is also searched when `paths` is empty (or the current directory with `recursive`).
+/// * `case_sensitive` - A flag indicating whether the search should be case-sensitive.
+/// * `ascii_case` - A flag indicating whether a case-insensitive literal search folds the case of ASCII letters only.
+/// * `regex` - A flag indicating whether the query is a regular expression rather than a literal string.
+/// * `invert` - A flag indicating whether the lines that do *not* match should be selected.
+/// * `line_number` - A flag indicating whether printed lines are prefixed with their line number.
+/// * `byte_offset` - A flag indicating whether printed lines are prefixed with the byte offset of their start.
+/// * `column` - A flag indicating whether printed lines are prefixed with the byte column of their first match.
+/// * `before_context` - The number of lines to print before each selected line.
+/// * `after_context` - The number of lines to print after each selected line.
+/// * `color` - When to highlight matches, paths and line numbers with ANSI colors.
+/// * `count` - A flag indicating whether only the number of selected lines is printed.
+/// * `files_with_matches` - A flag indicating whether only the paths of files with a selected line are printed.
+/// * `files_without_match` - A flag indicating whether only the paths of files without a selected line are printed.
+/// * `json` - A flag indicating whether results are written as JSON Lines events instead of text.
+/// * `quiet` - A flag indicating whether nothing is printed and the search stops at the first selected line.
+/// * `recursive` - A flag indicating whether directories are searched recursively.
+/// * `hidden` - A flag indicating whether hidden files and directories are searched when recursing.
+/// * `no_ignore` - A flag indicating whether ignore files are disregarded when recursing.
+/// * `globs` - Glob patterns selecting the files to search when recursing; those starting with `!` exclude files.
+/// * `iglobs` - Like `globs`, but matched case-insensitively.
+/// * `types` - Names of the file types to search when recursing, such as `rust`.
+/// * `types_not` - Names of the file types to skip when recursing.
+/// * `type_add` - File type definitions of the form `name:glob`, added to the built-in ones.
+/// * `search_zip` - A flag indicating whether compressed files are decompressed before they are searched.
+/// * `search_archives` - A flag indicating whether the members of tar and zip archives are searched.
+/// * `encoding` - The encoding input is transcoded from, or `None` to read UTF-8 unless a byte order mark says otherwise.
+/// * `output_encoding` - The encoding text output is written in, or `None` for UTF-8.
+/// * `raw_lines` - A flag indicating whether printed lines are written as the bytes read, without replacing invalid UTF-8.
+/// * `binary_files` - How files containing a NUL byte are searched.
+/// * `threads` - How many files are searched at once; 0 means one per available CPU.
+/// * `sort` - The order in which the results of each file are reported.
+///
+/// # Examples
+///
+/// ```
+/// use quewuigrep::Config;
+///
+/// let config = Config {
+///     query: "ERROR \\d{3}".into(),
+///     paths: vec!["app.log".into(), "app.log.1".into()],
+///     regex: true,
+///     ..Config::default()
+/// };
+/// assert_eq!(config.paths.len(), 2);
+/// assert!(config.case_sensitive);
+/// assert!(config.regex);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub query: String,
+    pub paths: Vec<OsString>,
+    pub case_sensitive: bool,
+    pub ascii_case: bool,
+    pub regex: bool,
+    pub invert: bool,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub column: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub color: ColorChoice,
+    pub count: bool,
+    pub files_with_matches: bool,
+    pub files_without_match: bool,
+    pub json: bool,
+    pub quiet: bool,
+    pub recursive: bool,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    pub globs: Vec<String>,
+    pub iglobs: Vec<String>,
+    pub types: Vec<String>,
+    pub types_not: Vec<String>,
+    pub type_add: Vec<String>,
+    pub search_zip: bool,
+    pub search_archives: bool,
+    pub encoding: Option<&'static Encoding>,
+    pub output_encoding: Option<&'static Encoding>,
+    pub raw_lines: bool,
+    pub binary_files: BinaryFiles,
+    pub threads: usize,
+    pub sort: Sort,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            query: String::new(),
+            paths: Vec::new(),
+            case_sensitive: true,
+            ascii_case: false,
+            regex: false,
+            invert: false,
+            line_number: false,
+            byte_offset: false,
+            column: false,
+            before_context: 0,
+            after_context: 0,
+            color: ColorChoice::Auto,
+            count: false,
+            files_with_matches: false,
+            files_without_match: false,
+            json: false,
+            quiet: false,
+            recursive: false,
+            hidden: false,
+            no_ignore: false,
+            globs: Vec::new(),
+            iglobs: Vec::new(),
+            types: Vec::new(),
+            types_not: Vec::new(),
+            type_add: Vec::new(),
+            search_zip: false,
+            search_archives: false,
+            encoding: None,
+            output_encoding: None,
+            raw_lines: false,
+            binary_files: BinaryFiles::Report,
+            threads: 1,
+            sort: Sort::None,
+        }
+    }
+}
+
+impl Config {
+    /// Creates a new `Config` instance from command-line arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The command-line arguments, starting with the program name.
+    ///
+    /// Options may appear anywhere before a `--` terminator; see [`USAGE`] for the full list.
+    /// Case-insensitive search is also enabled by setting the `CASE_INSENSITIVE` environment
+    /// variable. Use [`Config::from_args`] to parse arguments without consulting the environment.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Config, ArgsError>` - Returns a `Config` instance if successful, or an error describing
+    ///   the offending argument. `--help` and `--version` are reported as [`ArgsError::Help`] and
+    ///   [`ArgsError::Version`] so the caller decides how to print them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use quewuigrep::Config;
+    /// use std::env;
+    ///
+    /// let config = Config::new(env::args_os()).unwrap();
+    /// println!("searching for {} in {:?}", config.query, config.paths);
+    /// ```
+    pub fn new<I, S>(args: I) -> Result<Config, ArgsError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let mut config = Config::from_args(args)?;
+
+        if env::var("CASE_INSENSITIVE").is_ok() {
+            config.case_sensitive = false;
+        }
+
+        Ok(config)
+    }
+
+    /// Creates a new `Config` instance from command-line arguments, like [`Config::new`],
+    /// but without consulting the environment.
+    ///
+    /// Paths are kept as they were given, so a file whose name is not valid UTF-8 can be
+    /// searched. Options, their values and the query must be valid UTF-8, or
+    /// [`ArgsError::NotUnicode`] is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use quewuigrep::Config;
+    ///
+    /// let config = Config::from_args(["quewuigrep", "-in", "nobody", "poem.txt"]).unwrap();
+    /// assert_eq!(config.query, "nobody");
+    /// assert_eq!(config.paths, vec!["poem.txt"]);
+    /// assert!(!config.case_sensitive);
+    /// assert!(config.line_number);
+    /// ```
+    pub fn from_args<I, S>(args: I) -> Result<Config, ArgsError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let mut args = args.into_iter().map(Into::into);
+        args.next();
+
+        let mut config = Config::default();
+        let mut positional = Vec::new();
+        let mut options_done = false;
+
+        while let Some(arg) = args.next() {
+            let arg = match arg.into_string() {
+                Ok(arg) => arg,
+                Err(arg) if options_done || !arg.as_encoded_bytes().starts_with(b"-") => {
+                    positional.push(arg);
+                    continue;
+                }
+                Err(arg) => return Err(not_unicode(arg)),
+            };
+            if options_done || arg == "-" || !arg.starts_with('-') {
+                positional.push(arg.into());
+            } else if arg == "--" {
+                options_done = true;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                if takes_value(name) {
+                    let value = match (inline, default_value(name)) {
+                        (Some(value), _) => value,
+                        (None, Some(value)) => value.to_string(),
+                        (None, None) => args
+                            .next()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("--{}", name)))?
+                            .into_string()
+                            .map_err(not_unicode)?,
+                    };
+                    config.apply_value(name, &value)?;
+                    continue;
+                }
+                // Check a value against a scratch config so `--help=x` is an error, not help.
+                let target = if inline.is_some() {
+                    &mut Config::default()
+                } else {
+                    &mut config
+                };
+                match target.apply(name) {
+                    Ok(false) => return Err(ArgsError::UnknownOption(format!("--{}", name))),
+                    _ if inline.is_some() => {
+                        return Err(ArgsError::UnexpectedValue(format!("--{}", name)))
+                    }
+                    result => result?,
+                };
+            } else {
+                let flags = &arg[1..];
+                for (index, flag) in flags.char_indices() {
+                    let name = short_flag(flag)
+                        .ok_or_else(|| ArgsError::UnknownOption(format!("-{}", flag)))?;
+                    if !takes_value(name) {
+                        config.apply(name)?;
+                        continue;
+                    }
+                    // The rest of the argument is the value, as in `-A3`, or else the next argument.
+                    let rest = &flags[index + flag.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("-{}", flag)))?
+                            .into_string()
+                            .map_err(not_unicode)?
+                    } else {
+                        rest.to_string()
+                    };
+                    config.apply_value(name, &value)?;
+                    break;
+                }
+            }
+        }
+
+        if config.json {
+            let conflict = [
+                (config.count, "--count"),
+                (config.files_with_matches, "--files-with-matches"),
+                (config.files_without_match, "--files-without-match"),
+            ];
+            if let Some((_, option)) = conflict.iter().find(|(set, _)| *set) {
+                return Err(ArgsError::Conflict("--json", option));
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        config.query = positional
+            .next()
+            .ok_or(ArgsError::MissingQuery)?
+            .into_string()
+            .map_err(not_unicode)?;
+        config.paths = positional.collect();
+
+        Ok(config)
+    }
+
+    /// Returns a builder for a configuration, starting from the defaults.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use quewuigrep::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .query("nobody")
+    ///     .path("poem.txt")
+    ///     .ignore_case(true)
+    ///     .line_number(true)
+    ///     .build();
+    /// assert_eq!(config.paths, vec!["poem.txt"]);
+    /// assert!(!config.case_sensitive);
+    /// ```
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Applies an option given by its long name without the leading `--`,
+    /// returning `Ok(false)` if there is no such option.
+    fn apply(&mut self, name: &str) -> Result<bool, ArgsError> {
+        match name {
+            "ignore-case" => self.case_sensitive = false,
+            "ascii-case" => self.ascii_case = true,
+            "regex" => self.regex = true,
+            "invert-match" => self.invert = true,
+            "line-number" => self.line_number = true,
+            "byte-offset" => self.byte_offset = true,
+            "column" => self.column = true,
+            "count" => self.count = true,
+            "json" => self.json = true,
+            "quiet" | "silent" => self.quiet = true,
+            "files-with-matches" => {
+                self.files_with_matches = true;
+                self.files_without_match = false;
+            }
+            "files-without-match" => {
+                self.files_without_match = true;
+                self.files_with_matches = false;
+            }
+            "recursive" => self.recursive = true,
+            "hidden" => self.hidden = true,
+            "no-ignore" => self.no_ignore = true,
+            "search-zip" => self.search_zip = true,
+            "search-archives" => self.search_archives = true,
+            "raw-lines" => self.raw_lines = true,
+            "help" => return Err(ArgsError::Help),
+            "version" => return Err(ArgsError::Version),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Applies an option that takes a value, given by its long name.
+    fn apply_value(&mut self, name: &str, value: &str) -> Result<(), ArgsError> {
+        match name {
+            "after-context" => self.after_context = parse_number(name, value)?,
+            "before-context" => self.before_context = parse_number(name, value)?,
+            "context" => {
+                self.after_context = parse_number(name, value)?;
+                self.before_context = self.after_context;
+            }
+            "color" | "colour" => {
+                self.color = match value {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: format!("--{}", name),
+                            value: value.to_string(),
+                        })
+                    }
+                }
+            }
+            "threads" => self.threads = parse_number(name, value)?,
+            "sort" => {
+                self.sort = match value {
+                    "none" => Sort::None,
+                    "path" => Sort::Path,
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: format!("--{}", name),
+                            value: value.to_string(),
+                        })
+                    }
+                }
+            }
+            "binary-files" => {
+                self.binary_files = match value {
+                    "report" => BinaryFiles::Report,
+                    "skip" => BinaryFiles::Skip,
+                    "text" => BinaryFiles::Text,
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: format!("--{}", name),
+                            value: value.to_string(),
+                        })
+                    }
+                }
+            }
+            "encoding" => {
+                self.encoding = match value {
+                    "auto" => None,
+                    _ => Some(parse_encoding(name, value)?),
+                }
+            }
+            "output-encoding" => self.output_encoding = Some(parse_encoding(name, value)?),
+            "glob" => self.globs.push(value.to_string()),
+            "iglob" => self.iglobs.push(value.to_string()),
+            "type" => self.types.push(value.to_string()),
+            "type-not" => self.types_not.push(value.to_string()),
+            "type-add" => self.type_add.push(value.to_string()),
+            _ => unreachable!("{} is listed in VALUE_OPTIONS", name),
+        }
+        Ok(())
+    }
+}
+
+/// Long names of the options that take a value.
+const VALUE_OPTIONS: &[&str] = &[
+    "after-context",
+    "before-context",
+    "context",
+    "color",
+    "colour",
+    "threads",
+    "sort",
+    "glob",
+    "iglob",
+    "type",
+    "type-not",
+    "type-add",
+    "binary-files",
+    "encoding",
+    "output-encoding",
+];
+
+/// Returns whether the option with this long name takes a value.
+fn takes_value(name: &str) -> bool {
+    VALUE_OPTIONS.contains(&name)
+}
+
+/// Returns the value used when an option with an optional value is given without `=`.
+///
+/// Such options never consume the next argument, so `--color query` keeps `query`.
+fn default_value(name: &str) -> Option<&'static str> {
+    match name {
+        "color" | "colour" => Some("auto"),
+        _ => None,
+    }
+}
+
+/// Parses the value of a numeric option.
+fn parse_number(name: &str, value: &str) -> Result<usize, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        option: format!("--{}", name),
+        value: value.to_string(),
+    })
+}
+
+/// Parses the value of an encoding option, which may be any WHATWG encoding label.
+fn parse_encoding(name: &str, value: &str) -> Result<&'static Encoding, ArgsError> {
+    Encoding::for_label(value.as_bytes()).ok_or_else(|| ArgsError::InvalidValue {
+        option: format!("--{}", name),
+        value: value.to_string(),
+    })
+}
+
+/// Maps a short option to the long name of the same option.
+fn short_flag(flag: char) -> Option<&'static str> {
+    let name = match flag {
+        'i' => "ignore-case",
+        'E' => "regex",
+        'v' => "invert-match",
+        'n' => "line-number",
+        'b' => "byte-offset",
+        'A' => "after-context",
+        'B' => "before-context",
+        'C' => "context",
+        'c' => "count",
+        'l' => "files-with-matches",
+        'L' => "files-without-match",
+        'q' => "quiet",
+        'r' => "recursive",
+        'j' => "threads",
+        'g' => "glob",
+        't' => "type",
+        'T' => "type-not",
+        'z' => "search-zip",
+        'h' => "help",
+        'V' => "version",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Builds a [`Config`] one option at a time, for library callers.
+///
+/// Every method sets one field of the configuration, named after the matching
+/// command-line option, and options that override each other on the command line,
+/// such as `files_with_matches` and `files_without_match`, do so here too.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets the string to search for.
+    pub fn query(mut self, query: impl Into<String>) -> ConfigBuilder {
+        self.config.query = query.into();
+        self
+    }
+
+    /// Adds a path to search in.
+    pub fn path(mut self, path: impl Into<OsString>) -> ConfigBuilder {
+        self.config.paths.push(path.into());
+        self
+    }
+
+    /// Adds several paths to search in.
+    pub fn paths<I, S>(mut self, paths: I) -> ConfigBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.config.paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets whether case is ignored, as `-i` does.
+    pub fn ignore_case(mut self, yes: bool) -> ConfigBuilder {
+        self.config.case_sensitive = !yes;
+        self
+    }
+
+    /// Sets whether ignoring case only folds ASCII letters, as `--ascii-case` does.
+    pub fn ascii_case(mut self, yes: bool) -> ConfigBuilder {
+        self.config.ascii_case = yes;
+        self
+    }
+
+    /// Sets whether the query is a regular expression, as `-E` does.
+    pub fn regex(mut self, yes: bool) -> ConfigBuilder {
+        self.config.regex = yes;
+        self
+    }
+
+    /// Sets whether the lines that do not match are selected, as `-v` does.
+    pub fn invert(mut self, yes: bool) -> ConfigBuilder {
+        self.config.invert = yes;
+        self
+    }
+
+    /// Sets whether printed lines are prefixed with their line number, as `-n` does.
+    pub fn line_number(mut self, yes: bool) -> ConfigBuilder {
+        self.config.line_number = yes;
+        self
+    }
+
+    /// Sets whether printed lines are prefixed with their byte offset, as `-b` does.
+    pub fn byte_offset(mut self, yes: bool) -> ConfigBuilder {
+        self.config.byte_offset = yes;
+        self
+    }
+
+    /// Sets whether printed lines are prefixed with the column of their first match, as `--column` does.
+    pub fn column(mut self, yes: bool) -> ConfigBuilder {
+        self.config.column = yes;
+        self
+    }
+
+    /// Sets the number of lines printed before each selected line, as `-B` does.
+    pub fn before_context(mut self, lines: usize) -> ConfigBuilder {
+        self.config.before_context = lines;
+        self
+    }
+
+    /// Sets the number of lines printed after each selected line, as `-A` does.
+    pub fn after_context(mut self, lines: usize) -> ConfigBuilder {
+        self.config.after_context = lines;
+        self
+    }
+
+    /// Sets the number of lines printed before and after each selected line, as `-C` does.
+    pub fn context(self, lines: usize) -> ConfigBuilder {
+        self.before_context(lines).after_context(lines)
+    }
+
+    /// Sets when output is colored, as `--color` does.
+    pub fn color(mut self, color: ColorChoice) -> ConfigBuilder {
+        self.config.color = color;
+        self
+    }
+
+    /// Sets whether only the number of selected lines is printed, as `-c` does.
+    pub fn count(mut self, yes: bool) -> ConfigBuilder {
+        self.config.count = yes;
+        self
+    }
+
+    /// Sets whether only the paths of files with a selected line are printed, as `-l` does.
+    pub fn files_with_matches(mut self, yes: bool) -> ConfigBuilder {
+        self.config.files_with_matches = yes;
+        if yes {
+            self.config.files_without_match = false;
+        }
+        self
+    }
+
+    /// Sets whether only the paths of files without a selected line are printed, as `-L` does.
+    pub fn files_without_match(mut self, yes: bool) -> ConfigBuilder {
+        self.config.files_without_match = yes;
+        if yes {
+            self.config.files_with_matches = false;
+        }
+        self
+    }
+
+    /// Sets whether results are written as JSON Lines, as `--json` does.
+    pub fn json(mut self, yes: bool) -> ConfigBuilder {
+        self.config.json = yes;
+        self
+    }
+
+    /// Sets whether nothing is printed and the search stops at the first selected line, as `-q` does.
+    pub fn quiet(mut self, yes: bool) -> ConfigBuilder {
+        self.config.quiet = yes;
+        self
+    }
+
+    /// Sets whether directories are searched recursively, as `-r` does.
+    pub fn recursive(mut self, yes: bool) -> ConfigBuilder {
+        self.config.recursive = yes;
+        self
+    }
+
+    /// Sets whether hidden files and directories are searched when recursing, as `--hidden` does.
+    pub fn hidden(mut self, yes: bool) -> ConfigBuilder {
+        self.config.hidden = yes;
+        self
+    }
+
+    /// Sets whether ignore files are disregarded when recursing, as `--no-ignore` does.
+    pub fn no_ignore(mut self, yes: bool) -> ConfigBuilder {
+        self.config.no_ignore = yes;
+        self
+    }
+
+    /// Adds a glob selecting the files to search when recursing, as `--glob` does.
+    /// A glob starting with `!` excludes the files it matches.
+    pub fn glob(mut self, glob: impl Into<String>) -> ConfigBuilder {
+        self.config.globs.push(glob.into());
+        self
+    }
+
+    /// Adds a glob matched case-insensitively, as `--iglob` does.
+    pub fn iglob(mut self, glob: impl Into<String>) -> ConfigBuilder {
+        self.config.iglobs.push(glob.into());
+        self
+    }
+
+    /// Adds a file type to search when recursing, as `-t` does.
+    pub fn file_type(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.config.types.push(name.into());
+        self
+    }
+
+    /// Adds a file type to skip when recursing, as `-T` does.
+    pub fn file_type_not(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.config.types_not.push(name.into());
+        self
+    }
+
+    /// Adds a file type definition of the form `name:glob`, as `--type-add` does.
+    pub fn type_add(mut self, definition: impl Into<String>) -> ConfigBuilder {
+        self.config.type_add.push(definition.into());
+        self
+    }
+
+    /// Sets whether compressed files are decompressed before they are searched, as `-z` does.
+    pub fn search_zip(mut self, yes: bool) -> ConfigBuilder {
+        self.config.search_zip = yes;
+        self
+    }
+
+    /// Sets whether the members of tar and zip archives are searched, as `--search-archives` does.
+    pub fn search_archives(mut self, yes: bool) -> ConfigBuilder {
+        self.config.search_archives = yes;
+        self
+    }
+
+    /// Sets the encoding input is transcoded from, as `--encoding` does; `None` reads
+    /// UTF-8 unless a byte order mark says otherwise.
+    pub fn encoding(mut self, encoding: Option<&'static Encoding>) -> ConfigBuilder {
+        self.config.encoding = encoding;
+        self
+    }
+
+    /// Sets the encoding text output is written in, as `--output-encoding` does; `None`
+    /// writes UTF-8.
+    pub fn output_encoding(mut self, encoding: Option<&'static Encoding>) -> ConfigBuilder {
+        self.config.output_encoding = encoding;
+        self
+    }
+
+    /// Sets whether printed lines are written as the bytes read, without replacing invalid
+    /// UTF-8, as `--raw-lines` does.
+    pub fn raw_lines(mut self, yes: bool) -> ConfigBuilder {
+        self.config.raw_lines = yes;
+        self
+    }
+
+    /// Sets how files containing a NUL byte are searched, as `--binary-files` does.
+    pub fn binary_files(mut self, binary_files: BinaryFiles) -> ConfigBuilder {
+        self.config.binary_files = binary_files;
+        self
+    }
+
+    /// Sets how many files are searched at once, as `-j` does; 0 means one per available CPU.
+    pub fn threads(mut self, threads: usize) -> ConfigBuilder {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Sets the order in which the results of each file are reported, as `--sort` does.
+    pub fn sort(mut self, sort: Sort) -> ConfigBuilder {
+        self.config.sort = sort;
+        self
+    }
+
+    /// Returns the configuration built so far.
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/// When output should be colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color output written to a terminal, unless the `NO_COLOR` environment variable is set.
+    Auto,
+    /// Always color output.
+    Always,
+    /// Never color output.
+    Never,
+}
+
+impl ColorChoice {
+    /// Decides whether to color output, given whether it is going to a terminal.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use quewuigrep::ColorChoice;
+    ///
+    /// assert!(ColorChoice::Always.enabled(false));
+    /// assert!(!ColorChoice::Never.enabled(true));
+    /// ```
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+/// How files that look binary, because they contain a NUL byte, are searched.
+///
+/// A file is binary when a NUL byte occurs in its first block of 64 KiB, or in any
+/// later line read before the search of the file ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFiles {
+    /// Search binary files, but instead of printing their lines, report that a line
+    /// matches and stop searching the file. Lines selected before a NUL byte was found
+    /// are printed as usual.
+    Report,
+    /// Stop searching a file as soon as it turns out to be binary, as if the rest of
+    /// it did not match.
+    Skip,
+    /// Search binary files as text.
+    Text,
+}
+
+/// The order in which the results of each file are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    /// Report each file as soon as it has been searched. With one thread this is the
+    /// order of the paths on the command line, with directories walked in sorted order;
+    /// with more, it depends on which files finish first.
+    None,
+    /// Report files sorted by path, whatever the number of threads.
+    Path,
+}
+
+/// An error produced while parsing command-line arguments.
+///
+/// `Help` and `Version` are not failures: they signal that the user asked for
+/// the usage text or version string instead of a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    /// `-h` or `--help` was given.
+    Help,
+    /// `-V` or `--version` was given.
+    Version,
+    /// An option that quewuigrep does not know, such as `-x` or `--frobnicate`.
+    UnknownOption(String),
+    /// A value was attached to an option that takes none, such as `--count=3`.
+    UnexpectedValue(String),
+    /// An option that takes a value was the last argument, such as a trailing `-A`.
+    MissingValue(String),
+    /// An option was given a value it cannot use, such as `--context=lots`.
+    InvalidValue { option: String, value: String },
+    /// Two options that cannot be used together were given.
+    Conflict(&'static str, &'static str),
+    /// No query was given.
+    MissingQuery,
+    /// An option, an option value or the query is not valid UTF-8; it is shown with the
+    /// invalid bytes replaced.
+    NotUnicode(String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::Version => write!(f, "version requested"),
+            ArgsError::UnknownOption(option) => write!(f, "unknown option '{}'", option),
+            ArgsError::UnexpectedValue(option) => {
+                write!(f, "option '{}' doesn't allow a value", option)
+            }
+            ArgsError::MissingValue(option) => write!(f, "option '{}' requires a value", option),
+            ArgsError::InvalidValue { option, value } => {
+                write!(f, "invalid value '{}' for option '{}'", value, option)
+            }
+            ArgsError::Conflict(first, second) => {
+                write!(
+                    f,
+                    "options '{}' and '{}' cannot be used together",
+                    first, second
+                )
+            }
+            ArgsError::MissingQuery => write!(f, "Didn't get a query string!"),
+            ArgsError::NotUnicode(arg) => write!(f, "argument '{}' is not valid UTF-8", arg),
+        }
+    }
+}
+
+/// The error for an argument that had to be valid UTF-8 but is not.
+fn not_unicode(arg: OsString) -> ArgsError {
+    ArgsError::NotUnicode(arg.to_string_lossy().into_owned())
+}
+
+impl Error for ArgsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::from_args(["quewuigrep"].iter().chain(args).copied())
+    }
+
+    #[test]
+    fn positional_only() {
+        let config = parse(&["query", "poem.txt"]).unwrap();
+        assert_eq!(config.query, "query");
+        assert_eq!(config.paths, vec!["poem.txt"]);
+        assert!(config.case_sensitive);
+        assert!(!config.invert);
+    }
+
+    #[test]
+    fn combined_short_and_long_flags() {
+        let config = parse(&["-inr", "query", "--count", "src", "-v"]).unwrap();
+        assert!(!config.case_sensitive);
+        assert!(config.line_number);
+        assert!(config.recursive);
+        assert!(config.count);
+        assert!(config.invert);
+        assert_eq!(config.paths, vec!["src"]);
+    }
+
+    #[test]
+    fn many_paths() {
+        let config = parse(&["query", "a.txt", "-n", "b.txt", "--", "-c.txt"]).unwrap();
+        assert_eq!(config.paths, vec!["a.txt", "b.txt", "-c.txt"]);
+        assert!(!config.count);
+    }
+
+    #[test]
+    fn no_path_means_standard_input() {
+        assert!(parse(&["query"]).unwrap().paths.is_empty());
+        assert_eq!(parse(&["query", "-"]).unwrap().paths, vec!["-"]);
+    }
+
+    #[test]
+    fn position_flags() {
+        let config = parse(&["-nb", "--column", "query"]).unwrap();
+        assert!(config.line_number);
+        assert!(config.byte_offset);
+        assert!(config.column);
+    }
+
+    #[test]
+    fn context_values() {
+        let config = parse(&["-nA2", "query", "-B", "1"]).unwrap();
+        assert!(config.line_number);
+        assert_eq!((config.before_context, config.after_context), (1, 2));
+
+        let config = parse(&["--context=3", "query", "--after-context", "0"]).unwrap();
+        assert_eq!((config.before_context, config.after_context), (3, 0));
+
+        assert_eq!(
+            parse(&["query", "-C"]),
+            Err(ArgsError::MissingValue("-C".into()))
+        );
+        assert_eq!(
+            parse(&["-Clots", "query"]),
+            Err(ArgsError::InvalidValue {
+                option: "--context".into(),
+                value: "lots".into()
+            })
+        );
+    }
+
+    #[test]
+    fn color_values() {
+        let config = parse(&["--color", "query"]).unwrap();
+        assert_eq!(config.color, ColorChoice::Auto);
+        assert_eq!(config.query, "query");
+        assert_eq!(
+            parse(&["--colour=never", "query"]).unwrap().color,
+            ColorChoice::Never
+        );
+        assert_eq!(
+            parse(&["--color=sometimes", "query"]),
+            Err(ArgsError::InvalidValue {
+                option: "--color".into(),
+                value: "sometimes".into()
+            })
+        );
+    }
+
+    #[test]
+    fn threads_and_sort() {
+        let config = parse(&["-j4", "--sort", "path", "query"]).unwrap();
+        assert_eq!(config.threads, 4);
+        assert_eq!(config.sort, Sort::Path);
+        assert_eq!(parse(&["query"]).unwrap().threads, 1);
+        assert_eq!(
+            parse(&["--sort=size", "query"]),
+            Err(ArgsError::InvalidValue {
+                option: "--sort".into(),
+                value: "size".into()
+            })
+        );
+    }
+
+    #[test]
+    fn encodings_take_labels() {
+        let config = parse(&["--encoding=latin1", "--output-encoding", "sjis", "q"]).unwrap();
+        assert_eq!(config.encoding, Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(config.output_encoding, Some(encoding_rs::SHIFT_JIS));
+        assert_eq!(parse(&["--encoding=auto", "q"]).unwrap().encoding, None);
+        assert_eq!(
+            parse(&["--encoding=klingon", "q"]),
+            Err(ArgsError::InvalidValue {
+                option: "--encoding".into(),
+                value: "klingon".into()
+            })
+        );
+    }
+
+    #[test]
+    fn binary_files_policy() {
+        assert_eq!(parse(&["query"]).unwrap().binary_files, BinaryFiles::Report);
+        let config = parse(&["--binary-files=skip", "query"]).unwrap();
+        assert_eq!(config.binary_files, BinaryFiles::Skip);
+        assert_eq!(
+            parse(&["--binary-files", "hex", "query"]),
+            Err(ArgsError::InvalidValue {
+                option: "--binary-files".into(),
+                value: "hex".into()
+            })
+        );
+    }
+
+    #[test]
+    fn filters_accumulate() {
+        let config = parse(&[
+            "-g*.rs",
+            "--glob",
+            "!*.min.js",
+            "-trust",
+            "-T",
+            "js",
+            "--type-add=web:*.html",
+            "query",
+        ])
+        .unwrap();
+        assert_eq!(config.globs, vec!["*.rs", "!*.min.js"]);
+        assert_eq!(config.types, vec!["rust"]);
+        assert_eq!(config.types_not, vec!["js"]);
+        assert_eq!(config.type_add, vec!["web:*.html"]);
+    }
+
+    #[test]
+    fn file_listing_modes_override_each_other() {
+        let config = parse(&["-lL", "query"]).unwrap();
+        assert!(!config.files_with_matches);
+        assert!(config.files_without_match);
+
+        let config = parse(&["--files-without-match", "-l", "query"]).unwrap();
+        assert!(config.files_with_matches);
+        assert!(!config.files_without_match);
+    }
+
+    #[test]
+    fn json_conflicts_with_summary_modes() {
+        assert!(parse(&["--json", "query"]).unwrap().json);
+        assert_eq!(
+            parse(&["--json", "-l", "query"]),
+            Err(ArgsError::Conflict("--json", "--files-with-matches"))
+        );
+    }
+
+    #[test]
+    fn quiet_aliases() {
+        assert!(parse(&["-q", "query"]).unwrap().quiet);
+        assert!(parse(&["--silent", "query"]).unwrap().quiet);
+        assert!(!parse(&["query"]).unwrap().quiet);
+    }
+
+    #[test]
+    fn builder_matches_parser() {
+        let built = Config::builder()
+            .query("nobody")
+            .paths(["a.txt", "b.txt"])
+            .ignore_case(true)
+            .context(2)
+            .files_without_match(true)
+            .files_with_matches(true)
+            .build();
+        let parsed = parse(&["-iC2", "-Ll", "nobody", "a.txt", "b.txt"]).unwrap();
+        assert_eq!(built, parsed);
+    }
+
+    #[test]
+    fn terminator_allows_dash_query() {
+        let config = parse(&["-n", "--", "-v", "poem.txt"]).unwrap();
+        assert_eq!(config.query, "-v");
+        assert!(config.line_number);
+        assert!(!config.invert);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_need_not_be_unicode() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let bad = OsStr::from_bytes(b"bad\xff.txt");
+        let args = [OsStr::new("quewuigrep"), OsStr::new("nobody"), bad];
+        assert_eq!(Config::from_args(args).unwrap().paths, vec![bad]);
+
+        let args = [OsStr::new("quewuigrep"), bad, OsStr::new("poem.txt")];
+        assert_eq!(
+            Config::from_args(args),
+            Err(ArgsError::NotUnicode("bad\u{fffd}.txt".into()))
+        );
+        let args = [
+            OsStr::new("quewuigrep"),
+            OsStr::new("-g"),
+            bad,
+            OsStr::new("q"),
+        ];
+        assert!(matches!(
+            Config::from_args(args),
+            Err(ArgsError::NotUnicode(_))
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse(&["-ix", "q", "f"]),
+            Err(ArgsError::UnknownOption("-x".into()))
+        );
+        assert_eq!(
+            parse(&["--nope", "q", "f"]),
+            Err(ArgsError::UnknownOption("--nope".into()))
+        );
+        assert_eq!(
+            parse(&["--count=3", "q", "f"]),
+            Err(ArgsError::UnexpectedValue("--count".into()))
+        );
+        assert_eq!(parse(&[]), Err(ArgsError::MissingQuery));
+        assert_eq!(parse(&["q", "f", "--help"]), Err(ArgsError::Help));
+        assert_eq!(parse(&["-V"]), Err(ArgsError::Version));
+        assert_eq!(
+            parse(&["--help=yes"]),
+            Err(ArgsError::UnexpectedValue("--help".into()))
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::{self, BufRead, BufReader};
+
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+use crate::READ_BUFFER_SIZE;
+
+/// A compression format recognised by the magic bytes at the start of its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// The magic bytes each format starts with, but for bzip2, whose `BZh` is too likely
+    /// to start plain text and is checked by [`is_bzip2`] instead.
+    const MAGIC: &'static [(&'static [u8], Compression)] = &[
+        (&[0x1f, 0x8b], Compression::Gzip),
+        (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Compression::Xz),
+        (&[0x28, 0xb5, 0x2f, 0xfd], Compression::Zstd),
+    ];
+
+    /// Returns the format whose magic bytes `head` starts with, if any.
+    pub(crate) fn detect(head: &[u8]) -> Option<Compression> {
+        if is_bzip2(head) {
+            return Some(Compression::Bzip2);
+        }
+        Compression::MAGIC
+            .iter()
+            .find(|(magic, _)| head.starts_with(magic))
+            .map(|&(_, compression)| compression)
+    }
+}
+
+/// Returns whether `head` starts a bzip2 stream: `BZh`, the block size from `1` to `9`,
+/// and then the magic of the first block, or of the end of the stream if it is empty.
+fn is_bzip2(head: &[u8]) -> bool {
+    const BLOCK_MAGIC: &[u8] = &[0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END_MAGIC: &[u8] = &[0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+    match head {
+        [b'B', b'Z', b'h', b'1'..=b'9', rest @ ..] => {
+            rest.starts_with(BLOCK_MAGIC) || rest.starts_with(END_MAGIC)
+        }
+        _ => false,
+    }
+}
+
+/// Returns a reader of the decompressed contents of `reader` if it starts with the magic
+/// bytes of a known compression format, or of its contents as they are otherwise.
+///
+/// Only the first block of `reader` is looked at, so nothing is read past it. Streams
+/// made of several concatenated members, as `cat a.gz b.gz` produces, are read to the
+/// end. Corrupt data is reported as an I/O error by the returned reader.
+pub(crate) fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let Some(compression) = Compression::detect(reader.fill_buf()?) else {
+        return Ok(Box::new(reader));
+    };
+    let decoded: Box<dyn io::Read + 'a> = match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    };
+    Ok(Box::new(BufReader::with_capacity(
+        READ_BUFFER_SIZE,
+        decoded,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    fn decompressed(data: &[u8]) -> String {
+        let mut out = String::new();
+        decompress(data).unwrap().read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn decompresses_each_format() {
+        let text = "I'm nobody!\nWho are you?\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        // Concatenated gzip members are read as one stream.
+        assert_eq!(decompressed(&[gzip.clone(), gzip].concat()), text.repeat(2));
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        bzip2.write_all(text.as_bytes()).unwrap();
+        assert_eq!(decompressed(&bzip2.finish().unwrap()), text);
+        let empty = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        assert_eq!(decompressed(&empty.finish().unwrap()), "");
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+        xz.write_all(text.as_bytes()).unwrap();
+        assert_eq!(decompressed(&xz.finish().unwrap()), text);
+
+        let zstd = zstd::encode_all(text.as_bytes(), 1).unwrap();
+        assert_eq!(decompressed(&zstd), text);
+
+        assert_eq!(decompressed(text.as_bytes()), text);
+    }
+
+    #[test]
+    fn text_that_starts_like_a_magic_is_not_decompressed() {
+        assert_eq!(
+            decompressed(b"BZh is text, nobody\n"),
+            "BZh is text, nobody\n"
+        );
+        assert_eq!(decompressed(b"BZh9"), "BZh9");
+    }
+
+    #[test]
+    fn corrupt_data_is_an_error() {
+        let mut out = Vec::new();
+        let mut reader = decompress(&[0x1f, 0x8b, 0x08, 0xff, 0xff][..]).unwrap();
+        assert!(reader.read_to_end(&mut out).is_err());
+    }
+}
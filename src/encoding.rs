@@ -0,0 +1,197 @@
+use std::io::{self, BufReader, Read, Write};
+
+use encoding_rs::{Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+
+use crate::{Config, READ_BUFFER_SIZE};
+
+/// Returns whether input starting with `head` has to be transcoded before it is searched:
+/// when `config.encoding` is set, or when `head` starts with a byte order mark.
+pub(crate) fn needs_decoding(head: &[u8], config: &Config) -> bool {
+    config.encoding.is_some() || Encoding::for_bom(head).is_some()
+}
+
+/// Wraps `reader` in a reader that transcodes it to UTF-8.
+///
+/// A byte order mark takes precedence over `config.encoding` and is removed. UTF-8 input
+/// is passed on unchanged, so invalid bytes are still replaced when lines are decoded,
+/// and input in any other encoding has its invalid sequences replaced with U+FFFD.
+pub(crate) fn decode<R: Read>(
+    reader: R,
+    config: &Config,
+) -> BufReader<DecodeReaderBytes<R, Vec<u8>>> {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(config.encoding)
+        .bom_override(true)
+        .strip_bom(true)
+        .utf8_passthru(true)
+        .build(reader);
+    BufReader::with_capacity(READ_BUFFER_SIZE, decoder)
+}
+
+/// A writer that takes UTF-8 and writes it to `out` in another encoding, for
+/// `--output-encoding`.
+///
+/// Characters the encoding cannot represent are written as `?`, as are bytes that are
+/// not valid UTF-8. A character split across two writes is held until it is complete.
+pub(crate) struct EncodingWriter<W> {
+    out: W,
+    target: Target,
+    /// The start of a character whose remaining bytes have not been written yet.
+    pending: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+/// How text is encoded. encoding_rs only decodes UTF-16, so it is encoded here.
+enum Target {
+    Utf16 { big_endian: bool },
+    Encoder(Encoder),
+}
+
+impl<W: Write> EncodingWriter<W> {
+    /// Creates a writer that encodes what is written to it as `encoding`.
+    pub(crate) fn new(out: W, encoding: &'static Encoding) -> EncodingWriter<W> {
+        let target = if encoding == UTF_16LE || encoding == UTF_16BE {
+            Target::Utf16 {
+                big_endian: encoding == UTF_16BE,
+            }
+        } else {
+            Target::Encoder(encoding.new_encoder())
+        };
+        EncodingWriter {
+            out,
+            target,
+            pending: Vec::new(),
+            buf: vec![0; 4096],
+        }
+    }
+
+    /// Encodes `text` and writes it to the underlying writer.
+    fn encode(&mut self, mut text: &str) -> io::Result<()> {
+        let encoder = match &mut self.target {
+            Target::Utf16 { big_endian } => {
+                let bytes: Vec<u8> = text
+                    .encode_utf16()
+                    .flat_map(|unit| {
+                        if *big_endian {
+                            unit.to_be_bytes()
+                        } else {
+                            unit.to_le_bytes()
+                        }
+                    })
+                    .collect();
+                return self.out.write_all(&bytes);
+            }
+            Target::Encoder(encoder) => encoder,
+        };
+        loop {
+            let (result, read, written) =
+                encoder.encode_from_utf8_without_replacement(text, &mut self.buf, false);
+            self.out.write_all(&self.buf[..written])?;
+            text = &text[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(_) => self.out.write_all(b"?")?,
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(data);
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    self.encode(text)?;
+                    rest = &[];
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    // The bytes were just checked to be valid UTF-8.
+                    self.encode(std::str::from_utf8(valid).unwrap())?;
+                    match err.error_len() {
+                        Some(len) => {
+                            self.out.write_all(b"?")?;
+                            rest = &after[len..];
+                        }
+                        None => {
+                            self.pending = after.to_vec();
+                            rest = &[];
+                        }
+                    }
+                }
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    use super::*;
+
+    fn decoded(input: &[u8], encoding: Option<&'static Encoding>) -> String {
+        let config = Config {
+            encoding,
+            ..Config::default()
+        };
+        assert!(needs_decoding(input, &config));
+        let mut out = Vec::new();
+        decode(input, &config).read_to_end(&mut out).unwrap();
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    fn encoded(chunks: &[&[u8]], encoding: &'static Encoding) -> Vec<u8> {
+        let mut writer = EncodingWriter::new(Vec::new(), encoding);
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.out
+    }
+
+    #[test]
+    fn decodes_boms_and_explicit_encodings() {
+        let utf16 = b"\xff\xfeh\0i\0\n\0";
+        assert_eq!(decoded(utf16, None), "hi\n");
+        // A byte order mark wins over the encoding asked for.
+        assert_eq!(decoded(utf16, Some(WINDOWS_1252)), "hi\n");
+        assert_eq!(decoded(b"\xef\xbb\xbfhi\xff", None), "hi\u{fffd}");
+        assert_eq!(decoded(b"caf\xe9", Some(WINDOWS_1252)), "café");
+        assert_eq!(decoded(b"\x93\xfa\x96\x7b", Some(SHIFT_JIS)), "日本");
+
+        let plain = Config::default();
+        assert!(!needs_decoding(b"caf\xe9", &plain));
+        let mut lines = decode(&b"a\nb\n"[..], &plain).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "a");
+    }
+
+    #[test]
+    fn encodes_output() {
+        assert_eq!(
+            encoded(&[b"caf\xc3\xa9 \xe2\x82\xac"], WINDOWS_1252),
+            b"caf\xe9 \x80"
+        );
+        assert_eq!(
+            encoded(&["日本\n".as_bytes()], SHIFT_JIS),
+            b"\x93\xfa\x96\x7b\n"
+        );
+        assert_eq!(encoded(&[b"hi"], UTF_16BE), b"\0h\0i");
+        // A character split across writes, one that cannot be encoded and an invalid byte.
+        assert_eq!(
+            encoded(&[b"caf\xc3", b"\xa9 \xe6\x97\xa5 \xff!"], WINDOWS_1252),
+            b"caf\xe9 ? ?!"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::ArgsError;
+
+/// An error that stops quewuigrep from searching, or from searching a particular path.
+///
+/// Errors about a path carry that path and, where there is one, the underlying I/O
+/// error, so callers can tell a missing file from one they may not read.
+///
+/// # Examples
+///
+/// ```
+/// use quewuigrep::{run, Config, Error};
+///
+/// let config = Config {
+///     query: "(unclosed".into(),
+///     regex: true,
+///     ..Config::default()
+/// };
+/// assert!(matches!(run(config), Err(Error::Regex(_))));
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// The command-line arguments could not be parsed.
+    Args(ArgsError),
+    /// The query is not a valid regular expression.
+    Regex(regex::Error),
+    /// A glob or file type given to filter the files is not valid, or names no known type.
+    Filter(ignore::Error),
+    /// A path does not exist.
+    NotFound { path: PathBuf, source: io::Error },
+    /// A path exists but may not be read.
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// A path is a directory, and directories are only searched with `-r`.
+    IsDirectory { path: PathBuf },
+    /// Any other failure to read a path.
+    Io { path: PathBuf, source: io::Error },
+    /// The results could not be written.
+    Output(io::Error),
+    /// Some paths could not be searched; each was already reported on its own.
+    Incomplete { failed: usize },
+}
+
+impl Error {
+    /// Wraps an I/O error that occurred while reading `path`, picking the variant
+    /// that matches its kind.
+    pub(crate) fn io(path: &Path, source: io::Error) -> Error {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path, source },
+            io::ErrorKind::IsADirectory => Error::IsDirectory { path },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// Returns the path the error is about, if it is about one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::NotFound { path, .. }
+            | Error::PermissionDenied { path, .. }
+            | Error::IsDirectory { path }
+            | Error::Io { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Args(err) => write!(f, "{}", err),
+            Error::Regex(err) => write!(f, "{}", err),
+            Error::Filter(err) => write!(f, "{}", err),
+            Error::NotFound { path, .. } => {
+                write!(f, "{}: No such file or directory", path.display())
+            }
+            Error::PermissionDenied { path, .. } => {
+                write!(f, "{}: Permission denied", path.display())
+            }
+            Error::IsDirectory { path } => {
+                write!(
+                    f,
+                    "{}: Is a directory (use -r to search it)",
+                    path.display()
+                )
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Output(err) => write!(f, "could not write results: {}", err),
+            Error::Incomplete { failed: 1 } => write!(f, "1 path could not be searched"),
+            Error::Incomplete { failed } => write!(f, "{} paths could not be searched", failed),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Args(err) => Some(err),
+            Error::Regex(err) => Some(err),
+            Error::Filter(err) => Some(err),
+            Error::NotFound { source, .. }
+            | Error::PermissionDenied { source, .. }
+            | Error::Io { source, .. }
+            | Error::Output(source) => Some(source),
+            Error::IsDirectory { .. } | Error::Incomplete { .. } => None,
+        }
+    }
+}
+
+impl From<ArgsError> for Error {
+    fn from(err: ArgsError) -> Error {
+        Error::Args(err)
+    }
+}
+
+impl From<ignore::Error> for Error {
+    fn from(err: ignore::Error) -> Error {
+        Error::Filter(err)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::Regex(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_keep_their_path_and_kind() {
+        let path = Path::new("poem.txt");
+        let err = Error::io(path, io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(err, Error::NotFound { .. }));
+        assert_eq!(err.path(), Some(path));
+        assert_eq!(err.to_string(), "poem.txt: No such file or directory");
+
+        let err = Error::io(path, io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+
+        let err = Error::io(path, io::Error::other("disk on fire"));
+        assert_eq!(err.to_string(), "poem.txt: disk on fire");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn incomplete_counts_paths() {
+        assert_eq!(
+            Error::Incomplete { failed: 1 }.to_string(),
+            "1 path could not be searched"
+        );
+        assert_eq!(
+            Error::Incomplete { failed: 3 }.to_string(),
+            "3 paths could not be searched"
+        );
+        assert_eq!(Error::Incomplete { failed: 3 }.path(), None);
+    }
+}
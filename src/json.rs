@@ -0,0 +1,285 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    sink::{print_error, raw_offset, FileSummary, LineKind, Sink},
+    Error, LineMatch,
+};
+
+/// Appends `value` to `out` as a quoted JSON string.
+pub(crate) fn push_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Appends arbitrary bytes to `out` as a JSON object.
+///
+/// Valid UTF-8 is written as `{"text":"..."}`. Anything else is written as
+/// `{"bytes":"..."}` holding the standard base64 encoding of the bytes, so no
+/// information is lost to replacement characters.
+pub(crate) fn push_data(out: &mut String, bytes: &[u8]) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            out.push_str("{\"text\":");
+            push_string(out, text);
+        }
+        Err(_) => {
+            out.push_str("{\"bytes\":\"");
+            push_base64(out, bytes);
+            out.push('"');
+        }
+    }
+    out.push('}');
+}
+
+/// Appends the standard, padded base64 encoding of `bytes` to `out`.
+fn push_base64(out: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                out.push(ALPHABET[index as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+/// Totals reported in the JSON `end` and `summary` events.
+#[derive(Debug, Clone, Copy, Default)]
+struct Stats {
+    matched_lines: u64,
+    matches: u64,
+    bytes_searched: u64,
+}
+
+impl Stats {
+    fn add(&mut self, other: Stats) {
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.bytes_searched += other.bytes_searched;
+    }
+
+    fn push_json(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "\"matched_lines\":{},\"matches\":{},\"bytes_searched\":{}",
+            self.matched_lines, self.matches, self.bytes_searched
+        );
+    }
+}
+
+/// Writes search results as JSON Lines, one event per line, in the format of
+/// ripgrep's `--json`.
+///
+/// Each file produces a `begin` event, a `match` or `context` event for each line and
+/// an `end` event with the file's statistics and, if it is binary, the offset of its
+/// first NUL byte. `on_finish` writes a `summary` event with the totals. Lines keep
+/// the line ending they were read with, as in ripgrep. Paths, lines and submatches are written as `{"text":...}`, or as `{"bytes":...}` holding base64
+/// when they are not valid UTF-8, and submatch ranges are byte offsets into the
+/// original line.
+pub struct JsonSink<W> {
+    out: W,
+    file_stats: Stats,
+    total_stats: Stats,
+    searches: u64,
+    searches_with_match: u64,
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Creates a sink that writes events to `out`.
+    pub fn new(out: W) -> JsonSink<W> {
+        JsonSink {
+            out,
+            file_stats: Stats::default(),
+            total_stats: Stats::default(),
+            searches: 0,
+            searches_with_match: 0,
+        }
+    }
+
+    /// Writes one line as a JSON `match` or `context` event.
+    fn line(
+        &mut self,
+        path: &Path,
+        kind: LineKind,
+        found: &LineMatch<'_>,
+        raw: &[u8],
+    ) -> io::Result<()> {
+        let event_type = match kind {
+            LineKind::Match => "match",
+            LineKind::Context => "context",
+        };
+        let mut event = format!("{{\"type\":\"{}\",\"data\":{{\"path\":", event_type);
+        push_data(&mut event, path.as_os_str().as_encoded_bytes());
+        event.push_str(",\"lines\":");
+        push_data(&mut event, raw);
+        let _ = write!(
+            event,
+            ",\"line_number\":{},\"absolute_offset\":{},\"submatches\":[",
+            found.line_number, found.byte_offset
+        );
+        for (index, range) in found.matches.iter().enumerate() {
+            let range = raw_offset(raw, range.start)..raw_offset(raw, range.end);
+            if index > 0 {
+                event.push(',');
+            }
+            event.push_str("{\"match\":");
+            push_data(&mut event, &raw[range.clone()]);
+            let _ = write!(event, ",\"start\":{},\"end\":{}}}", range.start, range.end);
+        }
+        event.push_str("]}}");
+        writeln!(self.out, "{}", event)
+    }
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    fn on_file_begin(&mut self, path: &Path) -> io::Result<()> {
+        self.file_stats = Stats::default();
+        let mut event = String::from("{\"type\":\"begin\",\"data\":{\"path\":");
+        push_data(&mut event, path.as_os_str().as_encoded_bytes());
+        event.push_str("}}");
+        writeln!(self.out, "{}", event)
+    }
+
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.file_stats.matched_lines += 1;
+        self.file_stats.matches += found.matches.len() as u64;
+        self.line(path, LineKind::Match, found, raw)
+    }
+
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.line(path, LineKind::Context, found, raw)
+    }
+
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        self.file_stats.bytes_searched = summary.bytes_searched;
+        self.searches += 1;
+        // A binary file can have selected lines that were not reported as matches.
+        if summary.selected > 0 {
+            self.searches_with_match += 1;
+        }
+        self.total_stats.add(self.file_stats);
+
+        let mut event = String::from("{\"type\":\"end\",\"data\":{\"path\":");
+        push_data(&mut event, summary.path.as_os_str().as_encoded_bytes());
+        event.push_str(",\"stats\":{");
+        self.file_stats.push_json(&mut event);
+        event.push_str("},\"binary_offset\":");
+        match summary.binary_offset {
+            Some(offset) => {
+                let _ = write!(event, "{}", offset);
+            }
+            None => event.push_str("null"),
+        }
+        event.push_str("}}");
+        writeln!(self.out, "{}", event)
+    }
+
+    fn on_error(&mut self, err: Error) {
+        print_error(&err);
+    }
+
+    fn on_finish(&mut self) -> io::Result<()> {
+        let mut event = format!(
+            "{{\"type\":\"summary\",\"data\":{{\"stats\":{{\"searches\":{},\"searches_with_match\":{},",
+            self.searches, self.searches_with_match
+        );
+        self.total_stats.push_json(&mut event);
+        event.push_str("}}}");
+        writeln!(self.out, "{}", event)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = String::new();
+        push_string(&mut out, "say \"hi\"\\\t\u{1}é");
+        assert_eq!(out, r#""say \"hi\"\\\t\u0001é""#);
+    }
+
+    #[test]
+    fn invalid_utf8_is_base64() {
+        let mut out = String::new();
+        push_data(&mut out, b"ok");
+        push_data(&mut out, b"a\xffbc");
+        push_data(&mut out, b"\xff");
+        assert_eq!(out, r#"{"text":"ok"}{"bytes":"Yf9iYw=="}{"bytes":"/w=="}"#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_are_base64() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut out = Vec::new();
+        let mut sink = JsonSink::new(&mut out);
+        let path = Path::new(OsStr::from_bytes(b"bad\xff.log"));
+        sink.on_file_begin(path).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"type\":\"begin\",\"data\":{\"path\":{\"bytes\":\"YmFk/y5sb2c=\"}}}\n"
+        );
+    }
+
+    #[test]
+    fn events_report_raw_bytes() {
+        let mut out = Vec::new();
+        let mut sink = JsonSink::new(&mut out);
+        let found = LineMatch {
+            line_number: 2,
+            byte_offset: 12,
+            line: "\u{fffd} ab",
+            matches: std::iter::once(4..6).collect(),
+        };
+        sink.on_file_begin(Path::new("a\"b.log")).unwrap();
+        sink.on_match(Path::new("a\"b.log"), &found, b"\xff ab\r\n")
+            .unwrap();
+        sink.on_file_end(&FileSummary {
+            path: "a\"b.log".into(),
+            selected: 1,
+            bytes_searched: 20,
+            binary_offset: None,
+        })
+        .unwrap();
+        sink.on_finish().unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"type":"begin","data":{"path":{"text":"a\"b.log"}}}"#,
+                r#"{"type":"match","data":{"path":{"text":"a\"b.log"},"lines":{"bytes":"/yBhYg0K"},"line_number":2,"absolute_offset":12,"submatches":[{"match":{"text":"ab"},"start":2,"end":4}]}}"#,
+                r#"{"type":"end","data":{"path":{"text":"a\"b.log"},"stats":{"matched_lines":1,"matches":1,"bytes_searched":20},"binary_offset":null}}"#,
+                r#"{"type":"summary","data":{"stats":{"searches":1,"searches_with_match":1,"matched_lines":1,"matches":1,"bytes_searched":20}}}"#,
+            ]
+        );
+    }
+}
@@ -1,108 +1,195 @@
-use std::{env, error::Error, fs};
+use std::{
+    io::{self, IsTerminal},
+    ops::{ControlFlow, Range},
+    thread,
+};
 
-/// Runs the search based on the provided configuration.
+use regex::RegexBuilder;
+
+use crate::{encoding::EncodingWriter, sink::Checked};
+
+mod archive;
+mod casefold;
+mod config;
+mod decompress;
+mod encoding;
+mod error;
+mod json;
+mod matcher;
+mod parallel;
+mod printer;
+mod search;
+mod sink;
+mod walk;
+
+pub use config::{ArgsError, BinaryFiles, ColorChoice, Config, ConfigBuilder, Sort, USAGE};
+pub use encoding_rs::Encoding;
+pub use error::Error;
+pub use json::JsonSink;
+pub use matcher::Matcher;
+pub use printer::TextSink;
+pub use regex::Regex;
+pub use sink::{CollectedLine, Collector, FileSummary, LineKind, Sink};
+
+/// The path that stands for standard input.
+const STDIN_PATH: &str = "-";
+
+/// How much of a file is read at a time.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Runs the search based on the provided configuration, printing the results to standard output.
 /// 
 /// # Arguments
 /// 
-/// * `config` - A `Config` struct containing the query, filename, and case sensitivity flag.
+/// * `config` - A `Config` struct containing the query, paths and search options.
+/// 
+/// Results are printed as text by a [`TextSink`], colored according to `config.color`, or as
+/// JSON Lines by a [`JsonSink`] when `config.json` is set. Text is written in
+/// `config.output_encoding` if it is set, while JSON is always UTF-8. Paths that cannot be
+/// searched are reported on standard error and skipped; see [`run_with_sink`] for the details
+/// of the search. With `config.quiet`, nothing is printed and the search ends at the first
+/// selected line.
 /// 
 /// # Returns
 /// 
-/// * `Result<(), Box<dyn Error>>` - Returns `Ok(())` if successful, or an error if something goes wrong.
+/// * `Result<bool, Error>` - Returns whether any line was selected (with `config.files_without_match`,
+///   whether any path was printed), or an error if something goes wrong: `Error::Regex` for an invalid
+///   regular expression when `config.regex` is set, `Error::Output` if the results could not be written,
+///   or `Error::Incomplete` if any path could not be searched. A quiet search that selects a line
+///   returns `Ok(true)` even if an earlier path failed.
 /// 
 /// # Examples
 /// 
-/// ```
+/// ```no_run
 /// use quewuigrep::{Config, run};
-/// use std::env;
+/// use std::{env, process};
 /// 
-/// let args: Vec<String> = vec!["program".into(), "query".into(), "filename.txt".into()];
-/// let config = Config::new(&args).unwrap();
-/// run(config).unwrap();
+/// let config = Config::new(env::args_os()).unwrap();
+/// let matched = run(config).unwrap();
+/// process::exit(if matched { 0 } else { 1 });
 /// ```
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.filename)?;
-
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
-    };
-    for line in results {
-        println!("{}", line);
+pub fn run(config: Config) -> Result<bool, Error> {
+    let stdout = io::stdout();
+    if config.json && !config.quiet {
+        return run_with_sink(&config, &mut JsonSink::new(stdout.lock()));
+    }
+    let color = config.color.enabled(stdout.is_terminal());
+    match config.output_encoding {
+        Some(encoding) => {
+            let out = EncodingWriter::new(stdout.lock(), encoding);
+            run_with_sink(&config, &mut TextSink::new(out, &config, color))
+        }
+        None => run_with_sink(&config, &mut TextSink::new(stdout.lock(), &config, color)),
     }
-    Ok(())
 }
 
-/// Holds the configuration for the search.
+/// Runs the search based on the provided configuration, passing the results to `sink`.
 /// 
-/// # Fields
+/// # Arguments
 /// 
-/// * `query` - The string to search for.
-/// * `filename` - The name of the file to search in.
-/// * `case_sensitive` - A flag indicating whether the search should be case-sensitive.
+/// * `config` - A `Config` struct containing the query, paths and search options.
+/// * `sink` - Receives each file, line and error as the search finds them.
+/// 
+/// Paths that cannot be searched are passed to [`Sink::on_error`] and skipped, so the
+/// remaining files are still searched. Standard input is searched when no path is
+/// given or a path is `-`. Every input is read a line at a time and each selected line is
+/// passed on as soon as it is found, so large files and pipelines never have to fit in memory.
+/// 
+/// With `config.threads` other than 1, several files are searched at once. The results of
+/// each file are then held until it has been searched, and passed on together, in path
+/// order with `config.sort` set to `Sort::Path` or else in the order the files finish.
+/// 
+/// # Returns
+/// 
+/// * `Result<bool, Error>` - The same as [`run`].
 /// 
 /// # Examples
 /// 
+/// ```no_run
+/// use quewuigrep::{run_with_sink, Collector, Config};
+/// 
+/// let config = Config::builder().query("nobody").path("poem.txt").build();
+/// let mut collector = Collector::new();
+/// let matched = run_with_sink(&config, &mut collector).unwrap();
+/// assert_eq!(matched, !collector.lines.is_empty());
 /// ```
-/// use quewuigrep::Config;
-/// use std::env;
-/// 
-/// let args: Vec<String> = vec!["program".into(), "query".into(), "filename.txt".into()];
-/// let config = Config::new(&args).unwrap();
-/// assert_eq!(config.query, "query");
-/// assert_eq!(config.filename, "filename.txt");
-/// assert!(config.case_sensitive);
-/// ```
-pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool,
-}
+pub fn run_with_sink<S: Sink + ?Sized>(config: &Config, sink: &mut S) -> Result<bool, Error> {
+    // Compile the pattern and filters before touching any file so a bad regex or
+    // glob is reported even when the files are large or unreadable.
+    let matcher = Matcher::new(config)?;
+    let filters = walk::Filters::new(config)?;
 
-impl Config {
-    /// Creates a new `Config` instance from command-line arguments.
-    /// 
-    /// # Arguments
-    /// 
-    /// * `args` - An iterator over the command-line arguments.
-    /// 
-    /// # Returns
-    /// 
-    /// * `Result<Config, &'static str>` - Returns a `Config` instance if successful, or an error message if not.
-    /// 
-    /// # Examples
-    /// 
-    /// ```
-    /// use quewuigrep::Config;
-    /// use std::env;
-    /// 
-    /// let args: Vec<String> = vec!["program".into(), "query".into(), "filename.txt".into()];
-    /// let config = Config::new(&args).unwrap();
-    /// assert_eq!(config.query, "query");
-    /// assert_eq!(config.filename, "filename.txt");
-    /// assert!(config.case_sensitive);
-    /// ```
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
-        args.next();
-
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string!"),
-        };
+    let default_path = if config.recursive { "." } else { STDIN_PATH };
+    let paths = if config.paths.is_empty() {
+        &[default_path.into()][..]
+    } else {
+        &config.paths[..]
+    };
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a filename!"),
-        };
+    let mut failed = 0;
+    let mut files = walk::collect_files(paths, config, &filters, |err| {
+        sink.on_error(err);
+        failed += 1;
+    });
+    if config.sort == Sort::Path {
+        files.sort();
+    }
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
+    };
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+    let mut sink = Checked::new(sink);
+    let mut matched = false;
+    let flow = parallel::search_files(
+        &files,
+        threads,
+        &mut sink,
+        &matcher,
+        &filters,
+        config,
+        |sink, path, result| {
+            if let Some(err) = sink.failed.take() {
+                // The results could not be written, so there is no point in searching on.
+                return ControlFlow::Break(match err.kind() {
+                    // The reader of our output went away, as in `quewuigrep ... | head`.
+                    io::ErrorKind::BrokenPipe => Ok(matched),
+                    _ => Err(Error::Output(err)),
+                });
+            }
+            match result {
+                Ok(count) => {
+                    // `-L` succeeds when it lists a file, as in `grep`.
+                    matched |= if config.files_without_match {
+                        count == 0
+                    } else {
+                        count > 0
+                    };
+                    if matched && config.quiet {
+                        // The exit status is settled, so the other files need not be read.
+                        return ControlFlow::Break(Ok(true));
+                    }
+                }
+                Err(err) => {
+                    sink.on_error(Error::io(search::label(path), err));
+                    failed += 1;
+                }
+            }
+            ControlFlow::Continue(())
+        },
+    );
+    if let ControlFlow::Break(result) = flow {
+        return result;
+    }
+
+    match sink.on_finish() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(Error::Output(err)),
+        _ => {}
+    }
 
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive,
-        })
+    match failed {
+        0 => Ok(matched),
+        failed => Err(Error::Incomplete { failed }),
     }
 }
 
@@ -133,14 +220,14 @@ impl Config {
 /// assert_eq!(result, vec!["safe, fast, productive."]);
 /// ```
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
-        .collect()
+    lines(search_matches(&Matcher::literal(query), contents))
 }
 
 /// Searches for the query string in the contents, case-insensitive.
 /// 
+/// The query and the lines are compared by their Unicode case folding rather than by
+/// lowercasing them, so `STRASSE` finds `Straße` and `ΟΔΟΣ` finds `οδος`.
+/// 
 /// # Arguments
 /// 
 /// * `query` - The string to search for.
@@ -166,19 +253,191 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
 /// assert_eq!(result, vec!["Rust:", "Trust me."]);
 /// ```
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
+    lines(search_matches(&Matcher::case_insensitive(query), contents))
+}
+
+/// Compiles the query into a regular expression.
+/// 
+/// # Arguments
+/// 
+/// * `pattern` - The regular expression to compile.
+/// * `case_sensitive` - Whether the expression should match case-sensitively.
+/// 
+/// # Returns
+/// 
+/// * `Result<Regex, regex::Error>` - The compiled expression, or an error describing why the pattern is invalid.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use quewuigrep::build_regex;
+/// 
+/// let regex = build_regex(r"^fn \w+", true).unwrap();
+/// assert!(regex.is_match("fn main() {"));
+/// assert!(build_regex(r"ERROR \d{3", true).is_err());
+/// ```
+pub fn build_regex(pattern: &str, case_sensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+}
+
+/// Searches for lines in the contents that match a compiled regular expression.
+/// 
+/// # Arguments
+/// 
+/// * `regex` - The compiled regular expression, usually from [`build_regex`].
+/// * `contents` - The contents of the file to search in.
+/// 
+/// # Returns
+/// 
+/// * `Vec<&str>` - A vector of lines that match the expression.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use quewuigrep::{build_regex, search_regex};
+/// 
+/// let regex = build_regex(r"ERROR \d{3}", true).unwrap();
+/// let contents = "\
+/// INFO started
+/// ERROR 503 upstream
+/// ERROR timeout";
+/// 
+/// let result = search_regex(&regex, contents);
+/// assert_eq!(result, vec!["ERROR 503 upstream"]);
+/// ```
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<&'a str> {
+    lines(search_matches(&Matcher::regex(regex.clone()), contents))
+}
+
+/// Searches the contents using every matching option in a configuration.
+/// 
+/// The query is matched case-sensitively or not, and as a literal or a regular
+/// expression, according to `config`. With `config.invert` set, the lines that do
+/// *not* match are returned instead.
+/// 
+/// # Arguments
+/// 
+/// * `config` - The configuration whose `query`, `case_sensitive`, `regex` and `invert` fields are used.
+/// * `contents` - The contents of the file to search in.
+/// 
+/// # Returns
+/// 
+/// * `Result<Vec<&str>, regex::Error>` - The selected lines, or an error if `config.regex` is set
+///   and the query is not a valid regular expression.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use quewuigrep::{search_lines, Config};
+/// 
+/// let config = Config {
+///     query: "rUsT".into(),
+///     case_sensitive: false,
+///     invert: true,
+///     ..Config::default()
+/// };
+/// let contents = "\
+/// Rust:
+/// safe, fast, productive.
+/// Trust me.";
+/// 
+/// let result = search_lines(&config, contents).unwrap();
+/// assert_eq!(result, vec!["safe, fast, productive."]);
+/// ```
+pub fn search_lines<'a>(config: &Config, contents: &'a str) -> Result<Vec<&'a str>, regex::Error> {
+    let matcher = Matcher::new(config)?;
+    Ok(lines(select_lines(&matcher, contents, config.invert)))
+}
+
+/// A line selected by a search, with the position of every match in it.
+/// 
+/// # Fields
+/// 
+/// * `line_number` - The 1-based number of the line.
+/// * `byte_offset` - The byte offset of the start of the line within the searched contents.
+/// * `line` - The line itself, without its line ending.
+/// * `matches` - The byte ranges within `line` of every non-overlapping, non-empty match, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+    pub matches: Vec<Range<usize>>,
+}
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+/// Searches the contents and reports where each matching line and each match within it is.
+/// 
+/// Lines are selected under the same test as [`search`], [`search_case_insensitive`] and
+/// [`search_regex`], which are thin wrappers over this function.
+/// 
+/// # Arguments
+/// 
+/// * `matcher` - The compiled query.
+/// * `contents` - The contents of the file to search in.
+/// 
+/// # Returns
+/// 
+/// * `Vec<LineMatch>` - The matching lines with their positions and match ranges.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use quewuigrep::{search_matches, Matcher};
+/// 
+/// let contents = "\
+/// I'm nobody! Who are you?
+/// Are you nobody, too?";
+/// 
+/// let result = search_matches(&Matcher::literal("you"), contents);
+/// assert_eq!(result.len(), 2);
+/// assert_eq!(result[1].line_number, 2);
+/// assert_eq!(result[1].byte_offset, 25);
+/// assert_eq!(result[1].matches, vec![4..7]);
+/// ```
+pub fn search_matches<'a>(matcher: &Matcher, contents: &'a str) -> Vec<LineMatch<'a>> {
+    select_lines(matcher, contents, false)
+}
+
+/// Selects the lines that match, or with `invert` the lines that do not.
+fn select_lines<'a>(matcher: &Matcher, contents: &'a str, invert: bool) -> Vec<LineMatch<'a>> {
+    let mut byte_offset = 0;
+    let mut selected = Vec::new();
+
+    for (index, raw) in contents.split_inclusive('\n').enumerate() {
+        let line_offset = byte_offset;
+        byte_offset += raw.len();
+        // Strip the line ending the same way `str::lines` does.
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if matcher.find(line).is_some() == invert {
+            continue;
         }
+        selected.push(LineMatch {
+            line_number: index + 1,
+            byte_offset: line_offset,
+            line,
+            matches: if invert {
+                Vec::new()
+            } else {
+                matcher.find_all(line)
+            },
+        });
     }
-    results
+    selected
+}
+
+/// Keeps only the text of each selected line.
+fn lines(matches: Vec<LineMatch<'_>>) -> Vec<&str> {
+    matches.into_iter().map(|found| found.line).collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
 
     #[test]
@@ -207,4 +466,200 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn regex_pattern() {
+        let regex = build_regex(r"^fn \w+", true).unwrap();
+        let contents = "\
+fn main() {
+    let f = fn_ptr;
+}
+fn helper(x: u32) {}";
+
+        assert_eq!(
+            vec!["fn main() {", "fn helper(x: u32) {}"],
+            search_regex(&regex, contents)
+        );
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let regex = build_regex(r"error \d{3}", false).unwrap();
+        let contents = "\
+ERROR 404 not found
+error: 500";
+
+        assert_eq!(vec!["ERROR 404 not found"], search_regex(&regex, contents));
+    }
+
+    #[test]
+    fn regex_keeps_its_builder_options() {
+        let regex = RegexBuilder::new(r"^error")
+            .case_insensitive(true)
+            .multi_line(true)
+            .build()
+            .unwrap();
+        assert_eq!(search_regex(&regex, "ERROR 404\nok"), vec!["ERROR 404"]);
+
+        // Larger than the default size limit, which it was built with room for.
+        let regex = RegexBuilder::new(r"\w{250}")
+            .size_limit(1 << 30)
+            .build()
+            .unwrap();
+        assert!(search_regex(&regex, "short").is_empty());
+    }
+
+    #[test]
+    fn write_errors_are_not_read_errors() {
+        struct Full {
+            errors: Vec<Error>,
+        }
+        impl Sink for Full {
+            fn on_match(&mut self, _: &Path, _: &LineMatch<'_>, _: &[u8]) -> io::Result<()> {
+                Err(io::Error::other("No space left on device"))
+            }
+            fn on_error(&mut self, err: Error) {
+                self.errors.push(err);
+            }
+        }
+
+        let config = Config::builder()
+            .query("nobody")
+            .paths(["poem.txt", "poem.txt"])
+            .build();
+        let mut sink = Full { errors: Vec::new() };
+        let result = run_with_sink(&config, &mut sink);
+        assert!(matches!(result, Err(Error::Output(_))));
+        assert!(sink.errors.is_empty());
+    }
+
+    fn run_collecting(builder: ConfigBuilder) -> (Result<bool, Error>, Collector) {
+        let config = builder.threads(1).build();
+        let mut collector = Collector::new();
+        let result = run_with_sink(&config, &mut collector);
+        (result, collector)
+    }
+
+    #[test]
+    fn paths_that_cannot_be_searched_are_skipped() {
+        let (result, collector) = run_collecting(
+            Config::builder()
+                .query("nobody")
+                .paths(["poem.txt", "missing.txt"]),
+        );
+        assert!(matches!(result, Err(Error::Incomplete { failed: 1 })));
+        assert_eq!(collector.errors.len(), 1);
+        assert!(matches!(collector.errors[0], Error::NotFound { .. }));
+        assert_eq!(collector.errors[0].path(), Some(Path::new("missing.txt")));
+        // The other path is still searched.
+        assert_eq!(collector.lines.len(), 2);
+        assert_eq!(collector.files.len(), 1);
+
+        let (result, _) = run_collecting(Config::builder().query("everybody").path("poem.txt"));
+        assert!(matches!(result, Ok(false)));
+    }
+
+    #[test]
+    fn quiet_succeeds_despite_earlier_failures() {
+        let builder = Config::builder()
+            .query("nobody")
+            .paths(["missing.txt", "poem.txt"])
+            .quiet(true);
+        let (result, collector) = run_collecting(builder);
+        assert!(matches!(result, Ok(true)));
+        assert_eq!(collector.errors.len(), 1);
+        assert!(collector.lines.is_empty());
+
+        // Without a selected line, the failure still decides the outcome.
+        let builder = Config::builder()
+            .query("everybody")
+            .paths(["missing.txt", "poem.txt"])
+            .quiet(true);
+        let (result, _) = run_collecting(builder);
+        assert!(matches!(result, Err(Error::Incomplete { failed: 1 })));
+    }
+
+    #[test]
+    fn files_without_match_succeeds_when_a_path_is_listed() {
+        let builder = Config::builder()
+            .query("everybody")
+            .path("poem.txt")
+            .files_without_match(true);
+        let (result, collector) = run_collecting(builder);
+        assert!(matches!(result, Ok(true)));
+        assert_eq!(collector.files[0].selected, 0);
+
+        let builder = Config::builder()
+            .query("nobody")
+            .path("poem.txt")
+            .files_without_match(true);
+        let (result, collector) = run_collecting(builder);
+        assert!(matches!(result, Ok(false)));
+        assert_eq!(collector.files[0].selected, 1);
+        assert!(collector.lines.is_empty());
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(build_regex(r"ERROR \d{3", true).is_err());
+    }
+
+    #[test]
+    fn inverted_case_sensitive() {
+        let config = Config {
+            query: "duct".into(),
+            invert: true,
+            ..Config::default()
+        };
+        let contents = "\
+Rust:
+safe, fast, productive.
+Duct tape.";
+
+        assert_eq!(
+            vec!["Rust:", "Duct tape."],
+            search_lines(&config, contents).unwrap()
+        );
+    }
+
+    #[test]
+    fn inverted_case_insensitive() {
+        let config = Config {
+            query: "DUCT".into(),
+            case_sensitive: false,
+            invert: true,
+            ..Config::default()
+        };
+        let contents = "\
+Rust:
+safe, fast, productive.
+Duct tape.";
+
+        assert_eq!(vec!["Rust:"], search_lines(&config, contents).unwrap());
+    }
+
+    #[test]
+    fn structured_matches() {
+        let contents = "\
+Rust:\r
+Pick three.
+rust is trusted.";
+
+        let result = search_matches(&Matcher::case_insensitive("RUST"), contents);
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            (result[0].line_number, result[0].byte_offset, result[0].line),
+            (1, 0, "Rust:")
+        );
+        assert_eq!(result[0].matches.first(), Some(&(0..4)));
+        assert_eq!(
+            result[1],
+            LineMatch {
+                line_number: 3,
+                byte_offset: 19,
+                line: "rust is trusted.",
+                matches: vec![0..4, 9..13],
+            }
+        );
+    }
+}
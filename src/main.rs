@@ -1,24 +1,48 @@
 use std::{env, process};
 
-use quewuigrep::{run, Config};
+use quewuigrep::{run, ArgsError, Config, Error, USAGE};
+
+/// Exit status when at least one line was selected.
+const EXIT_MATCH: i32 = 0;
+/// Exit status when no line was selected.
+const EXIT_NO_MATCH: i32 = 1;
+/// Exit status when an error occurred, as in `grep`.
+const EXIT_ERROR: i32 = 2;
 
 /// Entry point of the application.
 ///
 /// The `main` function sets up the application, parses command-line arguments,
-/// and executes the main logic. If there is an error in configuration or execution,
-/// error messages are displayed and the process exits with a status code of 1.
+/// and executes the main logic. Like `grep`, the process exits with a status code of 0
+/// if a line was selected, 1 if none was, and 2 if there is an error in configuration
+/// or execution, in which case an error message is displayed.
 fn main() {
     // Create a new configuration from the command-line arguments.
-    // If there is an error parsing the arguments, print an error message and exit.
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
+    // `--help` and `--version` print their text and exit successfully; any other
+    // parsing problem prints an error message and exits.
+    let config = Config::new(env::args_os()).unwrap_or_else(|err| {
+        match err {
+            ArgsError::Help => println!("{}", USAGE),
+            ArgsError::Version => println!("quewuigrep {}", env!("CARGO_PKG_VERSION")),
+            err => {
+                eprintln!("Problem parsing arguments: {}", err);
+                eprintln!("Try 'quewuigrep --help' for more information.");
+                process::exit(EXIT_ERROR);
+            }
+        }
+        process::exit(0);
     });
 
     // Run the main logic of the application with the provided configuration.
-    // If there is an error during execution, print an error message and exit.
-    if let Err(e) = run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    // If there is an error during execution, print it after the program name, as `grep`
+    // does, and exit. Errors about a path start with that path. Each path that could not
+    // be searched was already reported, so `Error::Incomplete` only sets the status.
+    match run(config) {
+        Ok(true) => process::exit(EXIT_MATCH),
+        Ok(false) => process::exit(EXIT_NO_MATCH),
+        Err(Error::Incomplete { .. }) => process::exit(EXIT_ERROR),
+        Err(err) => {
+            eprintln!("quewuigrep: {}", err);
+            process::exit(EXIT_ERROR);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,415 @@
+use std::ops::Range;
+
+use memchr::memmem::Finder;
+use regex::bytes;
+
+use crate::{casefold::FoldedLine, Config, Regex};
+
+/// A compiled query that finds matches within a single line.
+///
+/// Lines are matched as bytes, so a line that is not valid UTF-8 can still match: the
+/// query is looked for in its valid parts, and invalid bytes match nothing but
+/// themselves.
+///
+/// # Examples
+///
+/// ```
+/// use quewuigrep::Matcher;
+///
+/// let matcher = Matcher::case_insensitive("NOBODY");
+/// assert_eq!(matcher.find("Who? Nobody."), Some(5..11));
+/// assert_eq!(matcher.find_bytes(b"\xffnobody"), Some(1..7));
+///
+/// let matcher = Matcher::case_insensitive("strasse");
+/// assert_eq!(matcher.find("Hauptstraße 1"), Some(5..12));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    kind: Kind,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Literal(Finder<'static>),
+    /// Holds the query with its case already folded; `ascii` folds only ASCII letters.
+    CaseInsensitive {
+        query: Finder<'static>,
+        ascii: bool,
+    },
+    Regex(bytes::Regex),
+    /// A regex compiled by the caller, used as given. It only matches text, so it is run
+    /// on each run of valid UTF-8 in a line.
+    StrRegex(Regex),
+}
+
+impl Matcher {
+    /// Creates the matcher described by the `query`, `case_sensitive`, `ascii_case` and `regex` fields of a configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Matcher, regex::Error>` - The matcher, or an error if `config.regex` is set and the
+    ///   query is not a valid regular expression.
+    pub fn new(config: &Config) -> Result<Matcher, regex::Error> {
+        Ok(if config.regex {
+            let regex = bytes::RegexBuilder::new(&config.query)
+                .case_insensitive(!config.case_sensitive)
+                .build()?;
+            Matcher {
+                kind: Kind::Regex(regex),
+            }
+        } else if config.case_sensitive {
+            Matcher::literal(&config.query)
+        } else if config.ascii_case {
+            Matcher::ascii_case_insensitive(&config.query)
+        } else {
+            Matcher::case_insensitive(&config.query)
+        })
+    }
+
+    /// Creates a matcher for a literal, case-sensitive query.
+    pub fn literal(query: &str) -> Matcher {
+        Matcher {
+            kind: Kind::Literal(Finder::new(query).into_owned()),
+        }
+    }
+
+    /// Creates a matcher for a literal query that ignores case, comparing the query and
+    /// lines by their Unicode full case folding, so that `STRASSE` matches `Straße`.
+    pub fn case_insensitive(query: &str) -> Matcher {
+        Matcher::folding(query, false)
+    }
+
+    /// Creates a matcher for a literal query that ignores the case of ASCII letters only.
+    ///
+    /// This is faster than [`Matcher::case_insensitive`], but other letters only match
+    /// themselves, so `É` does not match `é`.
+    pub fn ascii_case_insensitive(query: &str) -> Matcher {
+        Matcher::folding(query, true)
+    }
+
+    fn folding(query: &str, ascii: bool) -> Matcher {
+        let folded = FoldedLine::new(query.as_bytes(), ascii).text;
+        Matcher {
+            kind: Kind::CaseInsensitive {
+                query: Finder::new(&folded).into_owned(),
+                ascii,
+            },
+        }
+    }
+
+    /// Creates a matcher for a compiled regular expression, keeping every option it was
+    /// built with.
+    ///
+    /// In a line that is not valid UTF-8, the expression is matched against each run of
+    /// valid UTF-8 on its own, so a match never spans invalid bytes.
+    pub fn regex(regex: Regex) -> Matcher {
+        Matcher {
+            kind: Kind::StrRegex(regex),
+        }
+    }
+
+    /// Returns whether `line` contains a match, without working out where it is.
+    pub fn is_match(&self, line: &str) -> bool {
+        self.is_match_bytes(line.as_bytes())
+    }
+
+    /// Returns the byte range of the first match in `line`.
+    ///
+    /// A line matches under the same test as `search`, `search_case_insensitive` and
+    /// `search_regex` respectively.
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_bytes(line.as_bytes())
+    }
+
+    /// Returns the byte ranges of every non-overlapping, non-empty match in `line`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use quewuigrep::Matcher;
+    ///
+    /// let matcher = Matcher::literal("ab");
+    /// assert_eq!(matcher.find_all("ab cdab"), vec![0..2, 5..7]);
+    /// ```
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.find_all_bytes(line.as_bytes())
+    }
+
+    /// Returns whether `line`, which need not be valid UTF-8, contains a match.
+    pub fn is_match_bytes(&self, line: &[u8]) -> bool {
+        match &self.kind {
+            Kind::Literal(finder) => finder.find(line).is_some(),
+            Kind::CaseInsensitive { query, ascii } => {
+                find_folded(query, &FoldedLine::new(line, *ascii))
+                    .next()
+                    .is_some()
+            }
+            Kind::Regex(regex) => regex.is_match(line),
+            Kind::StrRegex(regex) => line
+                .utf8_chunks()
+                .any(|chunk| regex.is_match(chunk.valid())),
+        }
+    }
+
+    /// Returns the byte range of the first match in `line`, which need not be valid UTF-8.
+    ///
+    /// The range is widened to whole characters as in [`Matcher::find_all_bytes`].
+    pub fn find_bytes(&self, line: &[u8]) -> Option<Range<usize>> {
+        match &self.kind {
+            Kind::Literal(finder) => finder
+                .find(line)
+                .map(|start| start..start + finder.needle().len()),
+            Kind::CaseInsensitive { query, ascii } => {
+                find_folded(query, &FoldedLine::new(line, *ascii)).next()
+            }
+            Kind::Regex(regex) => {
+                whole_chars(line, regex.find_iter(line).map(|found| found.range()))
+                    .into_iter()
+                    .next()
+            }
+            Kind::StrRegex(regex) => find_iter_valid(regex, line).next(),
+        }
+    }
+
+    /// Returns the byte ranges of every non-overlapping, non-empty match in `line`, which
+    /// need not be valid UTF-8.
+    ///
+    /// Ranges never split a valid UTF-8 character, so for a valid line they can be used
+    /// to slice it as a `str`. A match of an expression that works on bytes, such as
+    /// `(?-u)\xc3`, that covers only part of a character is widened to the whole
+    /// character, and joined with any match it then overlaps.
+    pub fn find_all_bytes(&self, line: &[u8]) -> Vec<Range<usize>> {
+        let ranges: Vec<_> = match &self.kind {
+            Kind::Literal(finder) | Kind::CaseInsensitive { query: finder, .. }
+                if finder.needle().is_empty() =>
+            {
+                Vec::new()
+            }
+            Kind::Literal(finder) => find_iter(finder, line).collect(),
+            Kind::CaseInsensitive { query, ascii } => {
+                find_folded(query, &FoldedLine::new(line, *ascii)).collect()
+            }
+            Kind::Regex(regex) => {
+                whole_chars(line, regex.find_iter(line).map(|found| found.range()))
+            }
+            Kind::StrRegex(regex) => find_iter_valid(regex, line).collect(),
+        };
+        ranges
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+}
+
+/// Returns the ranges of the non-overlapping occurrences of the needle of `finder` in
+/// `haystack`, from left to right.
+fn find_iter<'a>(
+    finder: &'a Finder<'static>,
+    haystack: &'a [u8],
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let len = finder.needle().len();
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let start = at + finder.find(&haystack[at..])?;
+        at = start + len;
+        Some(start..at)
+    })
+}
+
+/// Returns the ranges of the matches of `regex` in each run of valid UTF-8 in `line`,
+/// from left to right.
+fn find_iter_valid<'a>(
+    regex: &'a Regex,
+    line: &'a [u8],
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    line.utf8_chunks()
+        .scan(0, |chunk_start, chunk| {
+            let start = *chunk_start;
+            *chunk_start += chunk.valid().len() + chunk.invalid().len();
+            Some((start, chunk.valid()))
+        })
+        .flat_map(move |(start, valid)| {
+            regex
+                .find_iter(valid)
+                .map(move |found| start + found.start()..start + found.end())
+        })
+}
+
+/// Widens each non-empty range in `ranges` so that it does not split a valid UTF-8
+/// character of `line`, joining the ranges that then overlap.
+fn whole_chars(line: &[u8], ranges: impl Iterator<Item = Range<usize>>) -> Vec<Range<usize>> {
+    let mut widened: Vec<Range<usize>> = Vec::new();
+    for range in ranges.filter(|range| !range.is_empty()) {
+        let range = widen_to_chars(line, range);
+        match widened.last_mut() {
+            Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+            _ => widened.push(range),
+        }
+    }
+    widened
+}
+
+/// Moves the start of `range` back and its end forward to the nearest boundaries of the
+/// valid UTF-8 characters of `line` they fall within. Positions in invalid bytes stay.
+fn widen_to_chars(line: &[u8], range: Range<usize>) -> Range<usize> {
+    let (mut start, mut end) = (range.start, range.end);
+    let mut chunk_start = 0;
+    for chunk in line.utf8_chunks() {
+        let valid = chunk.valid();
+        let valid_end = chunk_start + valid.len();
+        if (chunk_start..valid_end).contains(&start) {
+            while !valid.is_char_boundary(start - chunk_start) {
+                start -= 1;
+            }
+        }
+        if (chunk_start..valid_end).contains(&end) {
+            while !valid.is_char_boundary(end - chunk_start) {
+                end += 1;
+            }
+        }
+        chunk_start = valid_end + chunk.invalid().len();
+    }
+    start..end
+}
+
+/// Returns the ranges in the line of the non-overlapping occurrences of the folded
+/// query held by `finder` in `folded`, the folded line, from left to right.
+///
+/// An occurrence that starts or ends partway through the folding of one character does
+/// not count, so `s` does not match `ß`, which folds to `ss`, but `ss` does.
+fn find_folded<'a>(
+    finder: &'a Finder<'static>,
+    folded: &'a FoldedLine,
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let len = finder.needle().len();
+    let mut at = 0;
+    std::iter::from_fn(move || loop {
+        let start = at + finder.find(&folded.text[at..])?;
+        match folded.original_range(start..start + len) {
+            Some(range) => {
+                at = start + len;
+                return Some(range);
+            }
+            // A later occurrence may overlap this one, as `ss` does in the `sss` of `sß`.
+            None => at = start + 1,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(query: &str, case_sensitive: bool, regex: bool) -> Matcher {
+        let config = Config {
+            query: query.into(),
+            case_sensitive,
+            regex,
+            ..Config::default()
+        };
+        Matcher::new(&config).unwrap()
+    }
+
+    #[test]
+    fn first_match_ranges() {
+        assert_eq!(
+            matcher("ody", true, false).find("nobody, nobody"),
+            Some(3..6)
+        );
+        assert_eq!(matcher("ODY", true, false).find("nobody"), None);
+        assert_eq!(matcher(r"\d+", true, true).find("error 503"), Some(6..9));
+    }
+
+    #[test]
+    fn all_match_ranges() {
+        assert_eq!(
+            matcher("nobody", false, false).find_all("Nobody, NOBODY"),
+            vec![0..6, 8..14]
+        );
+        assert_eq!(matcher("o*", true, true).find_all("foo"), vec![1..3]);
+        assert!(matcher("", true, false).find_all("foo").is_empty());
+    }
+
+    #[test]
+    fn case_insensitive_ranges_point_into_the_original_line() {
+        // `İ` is two bytes but lowercases to three.
+        assert_eq!(matcher("rust", false, false).find("İİ RUST"), Some(5..9));
+        assert_eq!(matcher("ß", false, false).find("Straße"), Some(4..6));
+        assert_eq!(
+            matcher("RUST", false, false).find_bytes(b"\xc4\xff \xc4\xb0rust"),
+            Some(5..9)
+        );
+    }
+
+    #[test]
+    fn case_insensitive_queries_use_full_case_folding() {
+        assert_eq!(
+            matcher("STRASSE", false, false).find_all("Straße, strasse"),
+            vec![0..7, 9..16]
+        );
+        // Only whole foldings match: `s` is not found in `ß`, but `ss` is.
+        assert!(!Matcher::case_insensitive("s").is_match("ß"));
+        assert_eq!(Matcher::case_insensitive("ss").find("sß"), Some(1..3));
+        // A final sigma in the query matches any sigma, and the other way round.
+        assert_eq!(
+            matcher("ΟΔΟΣ", false, false).find_all("οδος οδοσ"),
+            vec![0..8, 9..17]
+        );
+        // Without the Turkic mappings, the dotless `ı` is a letter of its own.
+        assert!(!matcher("I", false, false).is_match("ı"));
+        assert!(matcher("ﬁle", false, false).is_match("FILE"));
+    }
+
+    #[test]
+    fn ascii_case_folds_only_ascii_letters() {
+        let config = Config {
+            query: "école".into(),
+            case_sensitive: false,
+            ascii_case: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        assert_eq!(matcher.find("L'éCOLE"), Some(2..8));
+        assert!(!matcher.is_match("L'ÉCOLE"));
+    }
+
+    #[test]
+    fn invalid_utf8_lines_still_match() {
+        let line = b"caf\xe9 \xff\xfeERROR 503\x80 error";
+        assert_eq!(matcher("ERROR", true, false).find_bytes(line), Some(7..12));
+        assert_eq!(
+            matcher("error", false, false).find_all_bytes(line),
+            vec![7..12, 18..23]
+        );
+        assert_eq!(matcher(r"\d+", true, true).find_bytes(line), Some(13..16));
+        // A caller's regex is run on the valid parts of the line.
+        let regex = crate::build_regex("error", false).unwrap();
+        assert_eq!(
+            Matcher::regex(regex).find_all_bytes(line),
+            vec![7..12, 18..23]
+        );
+        // Invalid bytes match only themselves, not U+FFFD.
+        assert!(!matcher("\u{fffd}", true, false).is_match_bytes(line));
+        assert_eq!(
+            matcher("(?-u)\\xff", true, true).find_bytes(line),
+            Some(5..6)
+        );
+    }
+
+    #[test]
+    fn byte_regexes_match_whole_characters() {
+        let line = "café".as_bytes();
+        assert_eq!(
+            matcher("(?-u)\\xc3", true, true).find_bytes(line),
+            Some(3..5)
+        );
+        assert_eq!(
+            matcher("(?-u)[\\xc3\\xa9]", true, true).find_all_bytes(line),
+            vec![3..5]
+        );
+        assert_eq!(
+            matcher("(?-u)\\xa9.", true, true).find_all_bytes(b"caf\xc3\xa9s \xa9x"),
+            vec![3..6, 7..9]
+        );
+    }
+}
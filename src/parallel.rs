@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::{ControlFlow, Range},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread,
+};
+
+use crate::{
+    matcher::Matcher,
+    search::search_path,
+    sink::{FileSummary, Sink},
+    walk::Filters,
+    Config, LineMatch, Sort,
+};
+
+/// A line passed to a [`Recording`], owned so it can be sent to another thread.
+struct RecordedLine {
+    path: PathBuf,
+    line_number: usize,
+    byte_offset: usize,
+    line: String,
+    matches: Vec<Range<usize>>,
+    raw: Vec<u8>,
+}
+
+impl RecordedLine {
+    fn new(path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> RecordedLine {
+        RecordedLine {
+            path: path.to_path_buf(),
+            line_number: found.line_number,
+            byte_offset: found.byte_offset,
+            line: found.line.to_string(),
+            matches: found.matches.clone(),
+            raw: raw.to_vec(),
+        }
+    }
+
+    fn as_line_match(&self) -> LineMatch<'_> {
+        LineMatch {
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            line: &self.line,
+            matches: self.matches.clone(),
+        }
+    }
+}
+
+/// One call made to a [`Recording`].
+enum Event {
+    Begin(PathBuf),
+    Match(RecordedLine),
+    Context(RecordedLine),
+    End(FileSummary),
+}
+
+/// A sink that records the results of one file on a worker thread, so they can be
+/// passed to the real sink on the main thread.
+#[derive(Default)]
+struct Recording {
+    events: Vec<Event>,
+}
+
+impl Recording {
+    /// Makes the recorded calls on `sink`, in the order they were made.
+    fn replay<S: Sink + ?Sized>(self, sink: &mut S) -> io::Result<()> {
+        for event in self.events {
+            match event {
+                Event::Begin(path) => sink.on_file_begin(&path)?,
+                Event::Match(line) => {
+                    sink.on_match(&line.path, &line.as_line_match(), &line.raw)?
+                }
+                Event::Context(line) => {
+                    sink.on_context(&line.path, &line.as_line_match(), &line.raw)?
+                }
+                Event::End(summary) => sink.on_file_end(&summary)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Recording {
+    fn on_file_begin(&mut self, path: &Path) -> io::Result<()> {
+        self.events.push(Event::Begin(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.events
+            .push(Event::Match(RecordedLine::new(path, found, raw)));
+        Ok(())
+    }
+
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.events
+            .push(Event::Context(RecordedLine::new(path, found, raw)));
+        Ok(())
+    }
+
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        self.events.push(Event::End(summary.clone()));
+        Ok(())
+    }
+}
+
+/// The outcome of searching the file at an index of the file list.
+type Searched = (usize, Recording, io::Result<u64>);
+
+/// Searches every file in `files`, passing the results to `sink`, and the outcome of
+/// each file to `on_result`, which may stop the search by breaking.
+///
+/// With more than one thread, files are searched concurrently by a pool of `threads`
+/// workers. The results of each file are recorded by its worker and passed to `sink`
+/// together on the calling thread, so lines of different files are never interleaved.
+/// With `Sort::Path`, files are passed on in the order of `files`, so the output is the
+/// same as with one thread; otherwise each file is passed on as soon as it has been
+/// searched.
+pub(crate) fn search_files<S, B, F>(
+    files: &[PathBuf],
+    threads: usize,
+    sink: &mut S,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+    mut on_result: F,
+) -> ControlFlow<B>
+where
+    S: Sink + ?Sized,
+    F: FnMut(&mut S, &Path, io::Result<u64>) -> ControlFlow<B>,
+{
+    if threads <= 1 || files.len() <= 1 {
+        for path in files {
+            let result = search_path(path, sink, matcher, filters, config);
+            on_result(sink, path, result)?;
+        }
+        return ControlFlow::Continue(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Searched>();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(files.len()) {
+            let sender = sender.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = files.get(index) else {
+                        break;
+                    };
+                    let mut recording = Recording::default();
+                    let result = search_path(path, &mut recording, matcher, filters, config);
+                    if sender.send((index, recording, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let ordered = config.sort == Sort::Path;
+        let flow = pass_on(receiver, ordered, files, sink, &mut on_result);
+        if flow.is_break() {
+            // Let the workers finish the files they are searching, but start no others.
+            stop.store(true, Ordering::Relaxed);
+        }
+        flow
+    })
+}
+
+/// Passes the results received from the workers to `sink`, in the order of `files` if
+/// `ordered` is set, or else in the order they arrive.
+fn pass_on<S, B, F>(
+    receiver: Receiver<Searched>,
+    ordered: bool,
+    files: &[PathBuf],
+    sink: &mut S,
+    on_result: &mut F,
+) -> ControlFlow<B>
+where
+    S: Sink + ?Sized,
+    F: FnMut(&mut S, &Path, io::Result<u64>) -> ControlFlow<B>,
+{
+    let mut waiting = BTreeMap::new();
+    let mut next_index = 0;
+    for (index, recording, result) in receiver {
+        if !ordered {
+            let result = recording.replay(sink).and(result);
+            on_result(sink, &files[index], result)?;
+            continue;
+        }
+        // Hold results back until every file before them has been passed on.
+        waiting.insert(index, (recording, result));
+        while let Some((recording, result)) = waiting.remove(&next_index) {
+            let result = recording.replay(sink).and(result);
+            on_result(sink, &files[next_index], result)?;
+            next_index += 1;
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::TextSink;
+
+    #[test]
+    fn sorted_parallel_output_matches_one_thread() {
+        let root = std::env::temp_dir().join(format!("quewuigrep-parallel-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let files: Vec<_> = (0..40)
+            .map(|i| {
+                let path = root.join(format!("{:02}.txt", i));
+                let lines = "nobody\nsomebody\n".repeat(i * 50);
+                fs::write(&path, lines).unwrap();
+                path
+            })
+            .collect();
+
+        let search = |threads| {
+            let config = Config {
+                query: "nobody".into(),
+                line_number: true,
+                recursive: true,
+                sort: Sort::Path,
+                ..Config::default()
+            };
+            let matcher = Matcher::new(&config).unwrap();
+            let filters = Filters::new(&config).unwrap();
+            let mut out = Vec::new();
+            let mut sink = TextSink::new(&mut out, &config, false);
+            let flow = search_files(
+                &files,
+                threads,
+                &mut sink,
+                &matcher,
+                &filters,
+                &config,
+                |_, _, result| {
+                    result.unwrap();
+                    ControlFlow::<()>::Continue(())
+                },
+            );
+            assert!(flow.is_continue());
+            out
+        };
+
+        let expected = search(1);
+        assert!(!expected.is_empty());
+        assert_eq!(search(8), expected);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
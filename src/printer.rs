@@ -0,0 +1,335 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crate::{
+    sink::{print_error, raw_offset, trim_line_ending, FileSummary, LineKind, Sink},
+    Config, Error, LineMatch,
+};
+
+/// ANSI escape sequences used when color is enabled, matching `grep`'s defaults.
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const PATH_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes search results in quewuigrep's plain text format.
+///
+/// Selected lines are prefixed with the fields requested by the configuration, and
+/// context lines use `-` instead of `:` after each field. With context enabled,
+/// groups of lines that are not adjacent are separated by `--`, as in `grep`.
+/// With `count`, `files_with_matches` or `files_without_match`, only the count or path
+/// of each file is written, and with `quiet` nothing is.
+///
+/// Lines are written with invalid UTF-8 replaced by U+FFFD, or as the bytes read with
+/// `raw_lines`.
+///
+/// When a binary file has selected lines that were not passed to the sink, `Binary file
+/// X matches` is written after the lines that were, as in `grep`.
+pub struct TextSink<W> {
+    out: W,
+    color: bool,
+    show_paths: bool,
+    line_number: bool,
+    column: bool,
+    byte_offset: bool,
+    context: bool,
+    count: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    quiet: bool,
+    raw_lines: bool,
+    /// Whether any line has been written, so the next group needs a `--` separator.
+    printed_group: bool,
+    /// The number of the last line written for the current file.
+    last_line: Option<usize>,
+    /// The number of selected lines written for the current file.
+    selected_lines: u64,
+}
+
+impl<W: Write> TextSink<W> {
+    /// Creates a sink that writes to `out`; `color` says whether ANSI colors should be written.
+    ///
+    /// Lines and counts are prefixed with the path they came from when more than one path
+    /// is given in `config.paths`, or when `config.recursive` or `config.search_archives`
+    /// is set, since the members of an archive are told apart by their paths.
+    pub fn new(out: W, config: &Config, color: bool) -> TextSink<W> {
+        TextSink {
+            out,
+            color,
+            show_paths: config.paths.len() > 1 || config.recursive || config.search_archives,
+            line_number: config.line_number,
+            column: config.column,
+            byte_offset: config.byte_offset,
+            context: config.before_context > 0 || config.after_context > 0,
+            count: config.count,
+            files_with_matches: config.files_with_matches,
+            files_without_match: config.files_without_match,
+            quiet: config.quiet,
+            raw_lines: config.raw_lines,
+            printed_group: false,
+            last_line: None,
+            selected_lines: 0,
+        }
+    }
+
+    /// Writes one line with the prefix fields requested by the configuration.
+    ///
+    /// `found.matches` are the byte ranges of the matches in `found.line`. The first one
+    /// gives the column of a selected line, counted in the bytes of `raw` as the byte
+    /// offset is, and all of them are highlighted when color is enabled. With `raw_lines`,
+    /// `raw` is written in place of `found.line`, though its line ending is still written
+    /// as `\n`.
+    fn line(
+        &mut self,
+        path: &Path,
+        kind: LineKind,
+        found: &LineMatch<'_>,
+        raw: &[u8],
+    ) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        // A line that does not follow the last one written starts a new group.
+        let follows = self
+            .last_line
+            .is_some_and(|last| found.line_number == last + 1);
+        if self.context && self.printed_group && !follows {
+            self.colored("--", SEPARATOR_COLOR)?;
+            writeln!(self.out)?;
+        }
+        self.printed_group = true;
+        self.last_line = Some(found.line_number);
+
+        let raw = trim_line_ending(raw);
+        let separator = separator(kind);
+        if self.show_paths {
+            self.field(path.display(), PATH_COLOR, separator)?;
+        }
+        if self.line_number {
+            self.field(found.line_number, NUMBER_COLOR, separator)?;
+        }
+        if self.column && kind == LineKind::Match {
+            // Inverted matches have no match position, so they report the start of the line.
+            let column = found
+                .matches
+                .first()
+                .map_or(0, |range| raw_offset(raw, range.start))
+                + 1;
+            self.field(column, NUMBER_COLOR, separator)?;
+        }
+        if self.byte_offset {
+            self.field(found.byte_offset, NUMBER_COLOR, separator)?;
+        }
+
+        if self.raw_lines {
+            let matches: Vec<_> = found
+                .matches
+                .iter()
+                .map(|range| raw_offset(raw, range.start)..raw_offset(raw, range.end))
+                .collect();
+            self.text(raw, &matches)
+        } else {
+            self.text(found.line.as_bytes(), &found.matches)
+        }
+    }
+
+    /// Writes the text of a line and its line ending, highlighting `matches` when color is
+    /// enabled.
+    fn text(&mut self, text: &[u8], matches: &[Range<usize>]) -> io::Result<()> {
+        let mut written = 0;
+        if self.color {
+            for range in matches {
+                self.out.write_all(&text[written..range.start])?;
+                self.out.write_all(MATCH_COLOR.as_bytes())?;
+                self.out.write_all(&text[range.clone()])?;
+                self.out.write_all(RESET.as_bytes())?;
+                written = range.end;
+            }
+        }
+        self.out.write_all(&text[written..])?;
+        writeln!(self.out)
+    }
+
+    /// Writes the path of a file on its own, for `--files-with-matches` and `--files-without-match`.
+    fn path(&mut self, path: &Path) -> io::Result<()> {
+        self.colored(path.display(), PATH_COLOR)?;
+        writeln!(self.out)
+    }
+
+    /// Writes a prefix field followed by its separator.
+    fn field(&mut self, value: impl Display, color: &str, separator: char) -> io::Result<()> {
+        self.colored(value, color)?;
+        self.colored(separator, SEPARATOR_COLOR)
+    }
+
+    /// Writes `value`, wrapped in `color` when color is enabled.
+    fn colored(&mut self, value: impl Display, color: &str) -> io::Result<()> {
+        if self.color {
+            write!(self.out, "{}{}{}", color, value, RESET)
+        } else {
+            write!(self.out, "{}", value)
+        }
+    }
+}
+
+impl<W: Write> Sink for TextSink<W> {
+    fn on_file_begin(&mut self, _path: &Path) -> io::Result<()> {
+        self.last_line = None;
+        self.selected_lines = 0;
+        Ok(())
+    }
+
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.selected_lines += 1;
+        self.line(path, LineKind::Match, found, raw)
+    }
+
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        self.line(path, LineKind::Context, found, raw)
+    }
+
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        if self.files_with_matches {
+            if summary.selected > 0 {
+                self.path(&summary.path)?;
+            }
+        } else if self.files_without_match {
+            if summary.selected == 0 {
+                self.path(&summary.path)?;
+            }
+        } else if self.count {
+            if self.show_paths {
+                self.field(summary.path.display(), PATH_COLOR, ':')?;
+            }
+            writeln!(self.out, "{}", summary.selected)?;
+        } else if summary.binary_offset.is_some() && summary.selected > self.selected_lines {
+            writeln!(self.out, "Binary file {} matches", summary.path.display())?;
+        }
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: Error) {
+        print_error(&err);
+    }
+
+    fn on_finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The character that follows each prefix field of a line of this kind, as in `grep`.
+fn separator(kind: LineKind) -> char {
+    match kind {
+        LineKind::Match => ':',
+        LineKind::Context => '-',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line_number: usize, line: &str, matches: Vec<Range<usize>>) -> LineMatch<'_> {
+        LineMatch {
+            line_number,
+            byte_offset: 12,
+            line,
+            matches,
+        }
+    }
+
+    #[test]
+    fn highlights_every_match_and_prefix() {
+        let config = Config {
+            line_number: true,
+            paths: vec!["poem.txt".into(), "other.txt".into()],
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out, &config, true);
+        let found = line(2, "ab cdab", vec![0..2, 5..7]);
+        sink.on_match(Path::new("poem.txt"), &found, b"ab cdab")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m\
+             \x1b[1;31mab\x1b[0m cd\x1b[1;31mab\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn separates_groups_that_are_not_adjacent() {
+        let config = Config {
+            line_number: true,
+            after_context: 1,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out, &config, false);
+        for path in [Path::new("a"), Path::new("b")] {
+            sink.on_file_begin(path).unwrap();
+            sink.on_match(path, &line(1, "x", Vec::new()), b"x")
+                .unwrap();
+            sink.on_context(path, &line(2, "y", Vec::new()), b"y")
+                .unwrap();
+            sink.on_match(path, &line(5, "x", Vec::new()), b"x")
+                .unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1:x\n2-y\n--\n5:x\n--\n1:x\n2-y\n--\n5:x\n"
+        );
+    }
+
+    #[test]
+    fn writes_raw_lines_when_asked() {
+        let raw = b"caf\xe9 nobody";
+        let found = line(1, "caf\u{fffd} nobody", vec![0..2, 7..13]);
+        for (raw_lines, expected) in [
+            (
+                false,
+                &b"\x1b[1;31mca\x1b[0mf\xef\xbf\xbd \x1b[1;31mnobody\x1b[0m\n"[..],
+            ),
+            (
+                true,
+                &b"\x1b[1;31mca\x1b[0mf\xe9 \x1b[1;31mnobody\x1b[0m\n"[..],
+            ),
+        ] {
+            let config = Config {
+                raw_lines,
+                ..Config::default()
+            };
+            let mut out = Vec::new();
+            let mut sink = TextSink::new(&mut out, &config, true);
+            sink.on_match(Path::new("poem.txt"), &found, raw).unwrap();
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn columns_count_the_bytes_read() {
+        let found = line(1, "\u{fffd}nobody nobody", vec![3..9, 10..16]);
+        for raw_lines in [false, true] {
+            let config = Config {
+                column: true,
+                raw_lines,
+                ..Config::default()
+            };
+            let mut out = Vec::new();
+            let mut sink = TextSink::new(&mut out, &config, false);
+            sink.on_match(Path::new("poem.txt"), &found, b"\xffnobody nobody\n")
+                .unwrap();
+            assert!(out.starts_with(b"2:"));
+        }
+    }
+}
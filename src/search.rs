@@ -0,0 +1,573 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use crate::{
+    archive,
+    decompress::decompress,
+    encoding,
+    matcher::Matcher,
+    sink::{decoded_offset, trim_line_ending, FileSummary, Sink},
+    walk::Filters,
+    BinaryFiles, Config, LineMatch, READ_BUFFER_SIZE, STDIN_PATH,
+};
+
+/// A line held back in case it turns out to be before-context for a later match.
+struct HeldLine {
+    number: usize,
+    offset: usize,
+    raw: Vec<u8>,
+}
+
+/// Searches the file at `path`, or standard input if `path` is `-`, with [`search_reader`].
+///
+/// With `config.search_zip`, compressed input is decompressed first; results still
+/// report `path`. With `config.search_archives`, compressed input is decompressed as
+/// well, and the members of tar and zip archives chosen by `filters` are searched
+/// instead of the archive itself.
+pub(crate) fn search_path<S: Sink + ?Sized>(
+    path: &Path,
+    sink: &mut S,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+) -> io::Result<u64> {
+    if path == Path::new(STDIN_PATH) {
+        let stdin = io::stdin().lock();
+        return search_input(stdin, sink, label(path), matcher, filters, config);
+    }
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, File::open(path)?);
+    if config.search_archives && archive::is_zip(reader.fill_buf()?) {
+        // A zip archive is read through its central directory, so the file is seeked
+        // instead of being read from start to end.
+        return archive::search_zip(reader, sink, path, matcher, filters, config);
+    }
+    search_input(reader, sink, path, matcher, filters, config)
+}
+
+/// Returns the path that results and errors for `path` are reported under, which is
+/// `(standard input)` for `-`, as in `grep`.
+pub(crate) fn label(path: &Path) -> &Path {
+    if path == Path::new(STDIN_PATH) {
+        Path::new("(standard input)")
+    } else {
+        path
+    }
+}
+
+/// Searches `reader` with [`search_reader`], or its members if it is an archive.
+fn search_input<R: BufRead, S: Sink + ?Sized>(
+    reader: R,
+    sink: &mut S,
+    path: &Path,
+    matcher: &Matcher,
+    filters: &Filters,
+    config: &Config,
+) -> io::Result<u64> {
+    if !config.search_zip && !config.search_archives {
+        return search_decoded(reader, sink, path, matcher, config);
+    }
+    let mut reader = decompress(reader)?;
+    if config.search_archives {
+        let head = reader.fill_buf()?;
+        if archive::is_tar(head) {
+            return archive::search_tar(reader, sink, path, matcher, filters, config);
+        }
+        if archive::is_zip(head) {
+            let reader = archive::buffer_zip(reader)?;
+            return archive::search_zip(reader, sink, path, matcher, filters, config);
+        }
+    }
+    search_decoded(reader, sink, path, matcher, config)
+}
+
+/// Searches `reader` with [`search_reader`], transcoding it to UTF-8 first if
+/// `config.encoding` is set or it starts with a byte order mark.
+pub(crate) fn search_decoded<R: BufRead, S: Sink + ?Sized>(
+    mut reader: R,
+    sink: &mut S,
+    path: &Path,
+    matcher: &Matcher,
+    config: &Config,
+) -> io::Result<u64> {
+    if encoding::needs_decoding(reader.fill_buf()?, config) {
+        search_reader(
+            encoding::decode(reader, config),
+            sink,
+            path,
+            matcher,
+            config,
+        )
+    } else {
+        search_reader(reader, sink, path, matcher, config)
+    }
+}
+
+/// Searches the lines read from `reader` one at a time and passes the selected ones to `sink`.
+///
+/// Returns the number of selected lines. With `config.count` only that number is
+/// reported, and with `config.files_with_matches`, `config.files_without_match` or
+/// `config.quiet` only whether it is zero matters, so reading stops at the first
+/// selected line. In these modes no line is passed to the sink, only the file summary.
+///
+/// With before or after context, the lines around each selected line are passed to the
+/// sink as well, each at most once and in file order.
+///
+/// Only the current line and the requested before-context are held in memory, so memory
+/// use does not grow with the size of the input. Lines are split the same way as
+/// `str::lines` and matched as bytes, so the valid parts of a line that is not valid
+/// UTF-8 can still match. The lines passed to the sink have invalid bytes replaced with
+/// U+FFFD, with the match ranges moved to fit, and their raw bytes and line ending
+/// alongside.
+///
+/// Unless `config.binary_files` is `BinaryFiles::Text`, the input is binary once a NUL
+/// byte is found in the first block read or in a later line. From then on, with
+/// `BinaryFiles::Skip` reading stops, and with `BinaryFiles::Report` no more lines are
+/// passed to the sink and reading stops at the next selected line, which is still
+/// counted. Counting and listing files are not affected by `BinaryFiles::Report`.
+pub(crate) fn search_reader<R: BufRead, S: Sink + ?Sized>(
+    mut reader: R,
+    sink: &mut S,
+    path: &Path,
+    matcher: &Matcher,
+    config: &Config,
+) -> io::Result<u64> {
+    let mut buf = Vec::new();
+    let mut line_number = 0;
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut before: VecDeque<HeldLine> = VecDeque::with_capacity(config.before_context);
+    let mut after_remaining = 0;
+    let stops_early = config.quiet || config.files_with_matches || config.files_without_match;
+    let detects_binary = config.binary_files != BinaryFiles::Text;
+    let mut binary_offset = None;
+
+    sink.on_file_begin(path)?;
+    if detects_binary {
+        binary_offset = find_nul(reader.fill_buf()?).map(|at| at as u64);
+    }
+    loop {
+        if binary_offset.is_some() && config.binary_files == BinaryFiles::Skip {
+            break;
+        }
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let line_offset = offset;
+        offset += read;
+        if detects_binary && binary_offset.is_none() {
+            binary_offset = find_nul(&buf).map(|at| (line_offset + at) as u64);
+            if binary_offset.is_some() && config.binary_files == BinaryFiles::Skip {
+                break;
+            }
+        }
+        let raw = trim_line_ending(&buf);
+
+        if stops_early || config.count {
+            // Only whether the line is selected matters, not where it matches.
+            if matcher.is_match_bytes(raw) != config.invert {
+                count += 1;
+                if stops_early {
+                    // One selected line settles whether the file is listed.
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let selected = matcher.is_match_bytes(raw) != config.invert;
+
+        if binary_offset.is_some() {
+            // The lines of a binary file are not shown; one selected line is enough.
+            if selected {
+                count += 1;
+                break;
+            }
+            continue;
+        }
+
+        if selected {
+            count += 1;
+            for held in before.drain(..) {
+                let text = String::from_utf8_lossy(trim_line_ending(&held.raw));
+                let context = LineMatch {
+                    line_number: held.number,
+                    byte_offset: held.offset,
+                    line: &text,
+                    matches: Vec::new(),
+                };
+                sink.on_context(path, &context, &held.raw)?;
+            }
+            let line = String::from_utf8_lossy(raw);
+            // Inverted matches are the lines without a match.
+            let mut matches = if config.invert {
+                Vec::new()
+            } else {
+                matcher.find_all_bytes(raw)
+            };
+            if let Cow::Owned(_) = line {
+                // Invalid bytes were replaced, so the ranges have to be moved to match.
+                for range in &mut matches {
+                    *range = decoded_offset(raw, range.start)..decoded_offset(raw, range.end);
+                }
+            }
+            let selected = LineMatch {
+                line_number,
+                byte_offset: line_offset,
+                line: &line,
+                matches,
+            };
+            sink.on_match(path, &selected, &buf)?;
+            after_remaining = config.after_context;
+        } else if after_remaining > 0 {
+            let line = String::from_utf8_lossy(raw);
+            let context = LineMatch {
+                line_number,
+                byte_offset: line_offset,
+                line: &line,
+                matches: Vec::new(),
+            };
+            sink.on_context(path, &context, &buf)?;
+            after_remaining -= 1;
+        } else if config.before_context > 0 {
+            if before.len() == config.before_context {
+                before.pop_front();
+            }
+            before.push_back(HeldLine {
+                number: line_number,
+                offset: line_offset,
+                raw: buf.clone(),
+            });
+        }
+    }
+
+    sink.on_file_end(&FileSummary {
+        path: path.to_path_buf(),
+        selected: count,
+        bytes_searched: offset as u64,
+        binary_offset,
+    })?;
+    Ok(count)
+}
+
+/// Returns the index of the first NUL byte in `bytes`, which marks them as binary.
+fn find_nul(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().position(|&byte| byte == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sink::LineKind, Collector, TextSink};
+
+    fn search(input: &[u8], path: Option<&str>, mut config: Config) -> String {
+        if let Some(path) = path {
+            // Searching more than one path shows the path of each line.
+            config.paths = vec![path.into(), "other.txt".into()];
+        }
+        let matcher = Matcher::new(&config).unwrap();
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out, &config, false);
+        let path = path.unwrap_or("(standard input)");
+        search_reader(input, &mut sink, Path::new(path), &matcher, &config).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reports_positions() {
+        let config = Config {
+            query: "you".into(),
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..Config::default()
+        };
+        let input = b"I'm nobody!\r\nWho are you?\nAre you nobody, too?";
+
+        assert_eq!(
+            search(input, None, config),
+            "2:9:13:Who are you?\n3:5:26:Are you nobody, too?\n"
+        );
+    }
+
+    #[test]
+    fn streams_lines_lossily() {
+        let config = Config {
+            query: "nobody".into(),
+            line_number: true,
+            ..Config::default()
+        };
+        let input = b"I'm nobody!\r\nWho are you?\nAre you nobody, \xfftoo?";
+
+        assert_eq!(
+            search(input, Some("poem.txt"), config),
+            "poem.txt:1:I'm nobody!\npoem.txt:3:Are you nobody, \u{fffd}too?\n"
+        );
+    }
+
+    #[test]
+    fn match_ranges_fit_the_decoded_line() {
+        let config = Config {
+            query: "too".into(),
+            case_sensitive: false,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut collector = Collector::new();
+        let input = b"\xff\xfeToo, \xe9 too\n";
+        search_reader(
+            &input[..],
+            &mut collector,
+            Path::new("poem.txt"),
+            &matcher,
+            &config,
+        )
+        .unwrap();
+
+        let found = &collector.lines[0];
+        assert_eq!(found.line, "\u{fffd}\u{fffd}Too, \u{fffd} too");
+        assert_eq!(found.matches, vec![6..9, 15..18]);
+        assert_eq!(&found.line[15..18], "too");
+    }
+
+    #[test]
+    fn match_ranges_hold_whole_characters() {
+        let config = Config {
+            query: r"(?-u)\xc3".into(),
+            regex: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut collector = Collector::new();
+        for input in [&b"caf\xc3\xa9\n"[..], b"\xff caf\xc3\xa9\n"] {
+            search_reader(input, &mut collector, Path::new("-"), &matcher, &config).unwrap();
+        }
+
+        let slices: Vec<_> = collector
+            .lines
+            .iter()
+            .map(|found| &found.line[found.matches[0].clone()])
+            .collect();
+        assert_eq!(slices, ["é", "é"]);
+    }
+
+    #[test]
+    fn context_windows_merge_and_separate() {
+        let config = Config {
+            query: "match".into(),
+            line_number: true,
+            before_context: 1,
+            after_context: 1,
+            ..Config::default()
+        };
+        let input = b"a\nmatch 2\nb\nmatch 4\nc\nd\ne\nmatch 8\n";
+
+        assert_eq!(
+            search(input, None, config),
+            "1-a\n2:match 2\n3-b\n4:match 4\n5-c\n--\n7-e\n8:match 8\n"
+        );
+    }
+
+    #[test]
+    fn inverted_count() {
+        let config = Config {
+            query: "you".into(),
+            case_sensitive: false,
+            invert: true,
+            count: true,
+            after_context: 2,
+            ..Config::default()
+        };
+        let input = b"I'm nobody!\nWho are YOU?\nAre you nobody, too?\n";
+
+        assert_eq!(search(input, Some("poem.txt"), config), "poem.txt:1\n");
+    }
+
+    #[test]
+    fn inverted_context_shows_matching_lines() {
+        let config = Config {
+            query: "x".into(),
+            invert: true,
+            after_context: 1,
+            ..Config::default()
+        };
+        let input = b"x1\nkeep\nx2\nx3\nkeep\n";
+
+        assert_eq!(
+            search(input, Some("f"), config),
+            "f:keep\nf-x2\n--\nf:keep\n"
+        );
+    }
+
+    #[test]
+    fn listing_stops_at_the_first_selected_line() {
+        let config = Config {
+            query: "nobody".into(),
+            files_with_matches: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut input: &[u8] = b"I'm nobody!\nWho are you?\n";
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out, &config, false);
+
+        let count = search_reader(
+            &mut input,
+            &mut sink,
+            Path::new("poem.txt"),
+            &matcher,
+            &config,
+        );
+        assert_eq!(count.unwrap(), 1);
+        assert_eq!(input, b"Who are you?\n");
+        assert_eq!(String::from_utf8(out).unwrap(), "poem.txt\n");
+    }
+
+    #[test]
+    fn quiet_prints_nothing_and_stops_early() {
+        let config = Config {
+            query: "nobody".into(),
+            quiet: true,
+            json: true,
+            line_number: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut input: &[u8] = b"I'm nobody!\nWho are you?\n";
+        let mut out = Vec::new();
+        let mut sink = TextSink::new(&mut out, &config, false);
+
+        let count = search_reader(
+            &mut input,
+            &mut sink,
+            Path::new("poem.txt"),
+            &matcher,
+            &config,
+        );
+        sink.on_finish().unwrap();
+        assert_eq!(count.unwrap(), 1);
+        assert_eq!(input, b"Who are you?\n");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn sink_receives_each_line_once() {
+        let config = Config {
+            query: "match".into(),
+            before_context: 2,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut collector = Collector::new();
+        let input: &[u8] = b"a\nmatch 2\nmatch 3\n";
+        search_reader(input, &mut collector, Path::new("f"), &matcher, &config).unwrap();
+
+        let lines: Vec<_> = collector
+            .lines
+            .iter()
+            .map(|line| (line.kind, line.line_number, line.matches.first().cloned()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (LineKind::Context, 1, None),
+                (LineKind::Match, 2, Some(0..5)),
+                (LineKind::Match, 3, Some(0..5)),
+            ]
+        );
+        assert_eq!(collector.files[0].selected, 2);
+        assert_eq!(collector.files[0].bytes_searched, 18);
+    }
+
+    #[test]
+    fn files_without_match() {
+        let config = Config {
+            query: "frog".into(),
+            files_without_match: true,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            search(b"I'm nobody!\n", None, config.clone()),
+            "(standard input)\n"
+        );
+        assert_eq!(search(b"How public, like a frog\n", None, config), "");
+    }
+
+    #[test]
+    fn binary_files_policies() {
+        let config = Config {
+            query: "nobody".into(),
+            line_number: true,
+            ..Config::default()
+        };
+        let early_nul = b"\0\x89PNG nobody\nnobody\n";
+        // Read in small blocks, so the NUL byte is only found when its line is read.
+        let late_nul = b"I'm nobody!\nWho are you?\n\0\nAre you nobody, too?\n";
+        let search_late = |config: &Config| {
+            let matcher = Matcher::new(config).unwrap();
+            let mut out = Vec::new();
+            let mut sink = TextSink::new(&mut out, config, false);
+            let reader = BufReader::with_capacity(8, &late_nul[..]);
+            search_reader(reader, &mut sink, Path::new("f"), &matcher, config).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            search(early_nul, Some("f"), config.clone()),
+            "Binary file f matches\n"
+        );
+        assert_eq!(
+            search_late(&config),
+            "1:I'm nobody!\nBinary file f matches\n"
+        );
+        assert_eq!(search(b"\0nothing here\n", Some("f"), config.clone()), "");
+
+        let skip = Config {
+            binary_files: BinaryFiles::Skip,
+            ..config.clone()
+        };
+        assert_eq!(search(early_nul, Some("f"), skip.clone()), "");
+        assert_eq!(search_late(&skip), "1:I'm nobody!\n");
+
+        let text = Config {
+            binary_files: BinaryFiles::Text,
+            ..config.clone()
+        };
+        assert_eq!(
+            search_late(&text),
+            "1:I'm nobody!\n4:Are you nobody, too?\n"
+        );
+
+        let mut collector = Collector::new();
+        let matcher = Matcher::new(&config).unwrap();
+        search_reader(
+            &late_nul[..],
+            &mut collector,
+            Path::new("f"),
+            &matcher,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(collector.files[0].binary_offset, Some(25));
+        assert_eq!(collector.files[0].selected, 1);
+        assert!(collector.lines.is_empty());
+    }
+
+    #[test]
+    fn standard_input_is_labelled_as_in_grep() {
+        assert_eq!(label(Path::new("-")), Path::new("(standard input)"));
+        assert_eq!(label(Path::new("./-")), Path::new("./-"));
+
+        let err = crate::Error::io(label(Path::new("-")), io::ErrorKind::Other.into());
+        assert!(err.to_string().starts_with("(standard input): "));
+    }
+}
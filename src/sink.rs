@@ -0,0 +1,280 @@
+use std::{
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, LineMatch};
+
+/// Whether a line was selected by the search or is context around a selected line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Match,
+    Context,
+}
+
+/// What a search found in one file, reported to [`Sink::on_file_end`].
+///
+/// # Fields
+///
+/// * `path` - The path of the file, or `(standard input)`.
+/// * `selected` - The number of selected lines. With `files_with_matches`, `files_without_match`
+///   or `quiet`, reading stops at the first selected line, so this is at most 1.
+/// * `bytes_searched` - How many bytes of the file were read.
+/// * `binary_offset` - The offset of the first NUL byte found, if the file turned out to be
+///   binary. Unless `binary_files` is `BinaryFiles::Text`, no line after it is passed to
+///   the sink, so `selected` may be more than the number of lines the sink received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub selected: u64,
+    pub bytes_searched: u64,
+    pub binary_offset: Option<u64>,
+}
+
+/// Receives the results of a search as [`run_with_sink`](crate::run_with_sink) finds them.
+///
+/// For every file, `on_file_begin` is called first, then `on_match` and `on_context` for
+/// each line to show, in file order, and finally `on_file_end`. Paths that cannot be
+/// searched are passed to `on_error` instead, and `on_finish` is called once at the end.
+///
+/// `on_match` and `on_context` are not called with `count`, `files_with_matches`,
+/// `files_without_match` or `quiet`; only `on_file_end` says what was found.
+///
+/// An I/O error returned by a sink stops the search, and `run_with_sink` returns it as
+/// `Error::Output`. A `BrokenPipe` error stops it quietly, as when the output is piped
+/// into `head`.
+pub trait Sink {
+    /// Called before a file is searched.
+    fn on_file_begin(&mut self, path: &Path) -> io::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Called with each selected line. `raw` holds the bytes `found.line` was decoded
+    /// from, which differ from it when they are not valid UTF-8, followed by the line
+    /// ending as it was read, if the line had one.
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()>;
+
+    /// Called with each context line around a selected line; `found.matches` is empty.
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        let _ = (path, found, raw);
+        Ok(())
+    }
+
+    /// Called after a file has been searched.
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        let _ = summary;
+        Ok(())
+    }
+
+    /// Called with each path that could not be searched. The search goes on with the
+    /// remaining paths.
+    fn on_error(&mut self, err: Error) {
+        let _ = err;
+    }
+
+    /// Called once every path has been searched.
+    fn on_finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A line collected by a [`Collector`].
+///
+/// # Fields
+///
+/// * `path` - The path of the file the line came from.
+/// * `kind` - Whether the line was selected or is context.
+/// * `line_number` - The 1-based number of the line.
+/// * `byte_offset` - The byte offset of the start of the line within the file.
+/// * `line` - The line without its line ending, with invalid UTF-8 replaced by U+FFFD.
+/// * `matches` - The byte ranges within `line` of every match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectedLine {
+    pub path: PathBuf,
+    pub kind: LineKind,
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: String,
+    pub matches: Vec<Range<usize>>,
+}
+
+/// A sink that keeps every result in memory, for callers that want to inspect them
+/// after the search instead of printing them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use quewuigrep::{run_with_sink, Collector, Config};
+///
+/// let config = Config::builder().query("nobody").path("poem.txt").build();
+/// let mut collector = Collector::new();
+/// run_with_sink(&config, &mut collector).unwrap();
+/// for line in &collector.lines {
+///     println!("{}:{}: {}", line.path.display(), line.line_number, line.line);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Collector {
+    pub lines: Vec<CollectedLine>,
+    pub files: Vec<FileSummary>,
+    pub errors: Vec<Error>,
+}
+
+impl Collector {
+    /// Creates an empty collector.
+    pub fn new() -> Collector {
+        Collector::default()
+    }
+
+    fn push(&mut self, path: &Path, kind: LineKind, found: &LineMatch<'_>) {
+        self.lines.push(CollectedLine {
+            path: path.to_path_buf(),
+            kind,
+            line_number: found.line_number,
+            byte_offset: found.byte_offset,
+            line: found.line.to_string(),
+            matches: found.matches.clone(),
+        });
+    }
+}
+
+impl Sink for Collector {
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, _raw: &[u8]) -> io::Result<()> {
+        self.push(path, LineKind::Match, found);
+        Ok(())
+    }
+
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, _raw: &[u8]) -> io::Result<()> {
+        self.push(path, LineKind::Context, found);
+        Ok(())
+    }
+
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        self.files.push(summary.clone());
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: Error) {
+        self.errors.push(err);
+    }
+}
+
+/// A sink that passes every call on to another and keeps the first error it returns, so
+/// that a failure to write the results can be told apart from a failure to read a file.
+pub(crate) struct Checked<'a, S: ?Sized> {
+    sink: &'a mut S,
+    /// The first error returned by `sink`.
+    pub(crate) failed: Option<io::Error>,
+}
+
+impl<'a, S: Sink + ?Sized> Checked<'a, S> {
+    pub(crate) fn new(sink: &'a mut S) -> Checked<'a, S> {
+        Checked { sink, failed: None }
+    }
+
+    /// Keeps the error of `result`, if any, and returns one of the same kind in its place.
+    fn check(&mut self, result: io::Result<()>) -> io::Result<()> {
+        result.map_err(|err| {
+            let kind = err.kind();
+            self.failed.get_or_insert(err);
+            io::Error::from(kind)
+        })
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Checked<'_, S> {
+    fn on_file_begin(&mut self, path: &Path) -> io::Result<()> {
+        let result = self.sink.on_file_begin(path);
+        self.check(result)
+    }
+
+    fn on_match(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        let result = self.sink.on_match(path, found, raw);
+        self.check(result)
+    }
+
+    fn on_context(&mut self, path: &Path, found: &LineMatch<'_>, raw: &[u8]) -> io::Result<()> {
+        let result = self.sink.on_context(path, found, raw);
+        self.check(result)
+    }
+
+    fn on_file_end(&mut self, summary: &FileSummary) -> io::Result<()> {
+        let result = self.sink.on_file_end(summary);
+        self.check(result)
+    }
+
+    fn on_error(&mut self, err: Error) {
+        self.sink.on_error(err);
+    }
+
+    fn on_finish(&mut self) -> io::Result<()> {
+        self.sink.on_finish()
+    }
+}
+
+/// Strips a trailing `\n` or `\r\n`, as `str::lines` does.
+pub(crate) fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Maps a byte offset in `String::from_utf8_lossy(raw)` to the same position in `raw`.
+///
+/// Each invalid sequence in `raw` became one three-byte U+FFFD in the decoded line,
+/// so offsets after it are shifted.
+pub(crate) fn raw_offset(raw: &[u8], decoded_offset: usize) -> usize {
+    let replacement_len = char::REPLACEMENT_CHARACTER.len_utf8();
+    let (mut raw_pos, mut decoded_pos) = (0, 0);
+    for chunk in raw.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if decoded_offset <= decoded_pos + valid {
+            return raw_pos + decoded_offset - decoded_pos;
+        }
+        raw_pos += valid;
+        decoded_pos += valid;
+
+        let invalid = chunk.invalid().len();
+        if invalid > 0 {
+            if decoded_offset < decoded_pos + replacement_len {
+                return raw_pos;
+            }
+            raw_pos += invalid;
+            decoded_pos += replacement_len;
+        }
+    }
+    raw_pos
+}
+
+/// Maps a byte offset in `raw` to the same position in `String::from_utf8_lossy(raw)`,
+/// the inverse of [`raw_offset`].
+///
+/// An offset inside an invalid sequence maps to the start of its U+FFFD.
+pub(crate) fn decoded_offset(raw: &[u8], raw_offset: usize) -> usize {
+    let replacement_len = char::REPLACEMENT_CHARACTER.len_utf8();
+    let (mut raw_pos, mut decoded_pos) = (0, 0);
+    for chunk in raw.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if raw_offset <= raw_pos + valid {
+            return decoded_pos + raw_offset - raw_pos;
+        }
+        raw_pos += valid;
+        decoded_pos += valid;
+
+        let invalid = chunk.invalid().len();
+        if invalid > 0 {
+            if raw_offset < raw_pos + invalid {
+                return decoded_pos;
+            }
+            raw_pos += invalid;
+            decoded_pos += replacement_len;
+        }
+    }
+    decoded_pos
+}
+
+/// Reports a path that could not be searched on standard error, as `grep` does.
+pub(crate) fn print_error(err: &Error) {
+    eprintln!("quewuigrep: {}", err);
+}
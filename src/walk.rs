@@ -0,0 +1,474 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{self, Path, PathBuf},
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    overrides::{Override, OverrideBuilder},
+    types::{Types, TypesBuilder},
+    Match,
+};
+
+use crate::{archive::has_archive_name, Config, Error};
+
+/// Expands the paths given on the command line into the list of files to search.
+///
+/// Files are kept in the order they were given. Directories are walked in sorted
+/// order when `config.recursive` is set; symbolic links found while walking are not
+/// followed, so a link cycle cannot make the walk loop forever. Without
+/// `config.recursive`, directories are reported through `on_error` as
+/// `Error::IsDirectory` and skipped, as are directories that cannot be read.
+///
+/// While walking, each entry is first checked against the globs of `filters`, which are
+/// matched against its path relative to the directory being walked, as in a `.gitignore`
+/// file. An entry matching an exclude glob (one starting with `!`) is skipped, and an
+/// entry matching an include glob is kept whatever the other rules say. When there are
+/// include globs, files matching none of them are skipped. Otherwise, hidden files and
+/// directories (those whose name starts with `.`) are skipped unless `config.hidden` is
+/// set, files and directories matched by ignore files are skipped unless
+/// `config.no_ignore` is set, and files are kept or skipped according to the file types
+/// of `filters`.
+///
+/// Paths given on the command line are always searched.
+///
+/// # Arguments
+///
+/// * `paths` - The paths given on the command line.
+/// * `config` - The configuration whose `recursive`, `hidden` and `no_ignore` fields are used.
+/// * `filters` - The globs and file types built from `config`.
+/// * `on_error` - Called with the error for every path that cannot be searched.
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - The files to search, in search order.
+pub fn collect_files<F>(
+    paths: &[OsString],
+    config: &Config,
+    filters: &Filters,
+    on_error: F,
+) -> Vec<PathBuf>
+where
+    F: FnMut(Error),
+{
+    let mut walker = Walker {
+        config,
+        filters,
+        root: PathBuf::new(),
+        files: Vec::new(),
+        rules: Vec::new(),
+        on_error,
+    };
+    for path in paths {
+        let path = Path::new(path);
+        if !path.is_dir() {
+            walker.files.push(path.to_path_buf());
+        } else if config.recursive {
+            walker.walk_root(path);
+        } else {
+            (walker.on_error)(Error::IsDirectory {
+                path: path.to_path_buf(),
+            });
+        }
+    }
+    walker.files
+}
+
+/// The globs and file types that choose which files are searched, built from
+/// `config.globs`, `config.iglobs`, `config.types`, `config.types_not` and
+/// `config.type_add`.
+pub(crate) struct Filters {
+    globs: Override,
+    types: Types,
+}
+
+impl Filters {
+    /// Builds the filters of `config`.
+    ///
+    /// Returns `Error::Filter` if a glob or type definition is invalid or a type name
+    /// is unknown.
+    pub(crate) fn new(config: &Config) -> Result<Filters, Error> {
+        Ok(Filters {
+            globs: build_globs(config)?,
+            types: build_types(config)?,
+        })
+    }
+
+    /// Returns `Some(true)` if an include glob is the last glob to match `path`,
+    /// `Some(false)` if an exclude glob is or include globs exist and none matches, and
+    /// `None` if the globs do not decide.
+    fn glob(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        match self.globs.matched(path, is_dir) {
+            Match::Ignore(_) => Some(false),
+            Match::Whitelist(_) => Some(true),
+            Match::None => None,
+        }
+    }
+
+    /// Returns whether the file types exclude `path`.
+    fn excludes_type(&self, path: &Path, is_dir: bool) -> bool {
+        self.types.matched(path, is_dir).is_ignore()
+    }
+
+    /// Returns whether the file at `path`, relative to the root of an archive, is left
+    /// out of the search by the globs or file types.
+    pub(crate) fn excludes_member(&self, path: &Path) -> bool {
+        match self.glob(path, false) {
+            Some(included) => !included,
+            None => self.excludes_type(path, false),
+        }
+    }
+}
+
+/// Compiles the `--glob` and `--iglob` patterns. Later patterns take precedence, and
+/// case-insensitive ones come after the others.
+fn build_globs(config: &Config) -> Result<Override, ignore::Error> {
+    // An empty root leaves the paths as they are; they are made relative before matching.
+    let mut builder = OverrideBuilder::new("");
+    for glob in &config.globs {
+        builder.add(glob)?;
+    }
+    builder.case_insensitive(true)?;
+    for glob in &config.iglobs {
+        builder.add(glob)?;
+    }
+    builder.build()
+}
+
+/// Builds the file types from the built-in definitions and `--type-add`, selecting and
+/// negating the types named by `-t` and `-T`.
+fn build_types(config: &Config) -> Result<Types, ignore::Error> {
+    let mut builder = TypesBuilder::new();
+    builder.add_defaults();
+    for definition in &config.type_add {
+        builder.add_def(definition)?;
+    }
+    for name in &config.types {
+        builder.select(name);
+    }
+    for name in &config.types_not {
+        builder.negate(name);
+    }
+    builder.build()
+}
+
+/// The state of a directory walk.
+struct Walker<'a, F> {
+    config: &'a Config,
+    filters: &'a Filters,
+    /// The directory given on the command line that is being walked.
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    /// The ignore rules of each directory from the outermost one to the one being
+    /// walked. Rules of inner directories take precedence.
+    rules: Vec<Gitignore>,
+    on_error: F,
+}
+
+impl<F: FnMut(Error)> Walker<'_, F> {
+    /// Walks a directory given on the command line.
+    ///
+    /// When the directory is inside a Git repository, the ignore files of the
+    /// directories between it and the root of the repository apply as well.
+    fn walk_root(&mut self, root: &Path) {
+        self.rules.clear();
+        self.root = root.to_path_buf();
+        // Ignore rules match absolute paths, so `.` and `src` are treated alike.
+        let absolute = path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut in_repo = false;
+        if !self.config.no_ignore {
+            let parents: Vec<_> = absolute.ancestors().skip(1).collect();
+            if let Some(repo) = parents.iter().position(|dir| is_repo_root(dir)) {
+                for dir in parents[..=repo].iter().rev() {
+                    self.push_rules(dir, true);
+                }
+                in_repo = true;
+            }
+        }
+        self.walk(root, &absolute, in_repo);
+    }
+
+    /// Appends every file below `dir` that is not hidden or ignored to `self.files`.
+    ///
+    /// `absolute` is the absolute form of `dir`, and `in_repo` is whether a directory
+    /// above `dir` is the root of a Git repository, so `.gitignore` files apply.
+    fn walk(&mut self, dir: &Path, absolute: &Path, in_repo: bool) {
+        let names = fs::read_dir(dir).and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<OsString>>>()
+        });
+        let mut names = match names {
+            Ok(names) => names,
+            Err(err) => return (self.on_error)(Error::io(dir, err)),
+        };
+        names.sort();
+
+        let depth = self.rules.len();
+        let in_repo = in_repo || (!self.config.no_ignore && is_repo_root(absolute));
+        if !self.config.no_ignore {
+            self.push_rules(absolute, in_repo);
+        }
+
+        for name in names {
+            let entry = dir.join(&name);
+            let metadata = match fs::symlink_metadata(&entry) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    (self.on_error)(Error::io(&entry, err));
+                    continue;
+                }
+            };
+            let absolute_entry = absolute.join(&name);
+            if self.is_skipped(&entry, &absolute_entry, metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                self.walk(&entry, &absolute_entry, in_repo);
+            } else if metadata.is_file() {
+                self.files.push(entry);
+            }
+        }
+        self.rules.truncate(depth);
+    }
+
+    /// Reads the ignore files of the directory at the absolute path `dir`.
+    ///
+    /// Within a directory, `.ignore` takes precedence over `.gitignore`, which takes
+    /// precedence over `.git/info/exclude`. The last two are only read inside a Git
+    /// repository. As in Git, lines that are not valid patterns are skipped, and so are
+    /// ignore files that cannot be read.
+    fn push_rules(&mut self, dir: &Path, in_repo: bool) {
+        let mut builder = GitignoreBuilder::new(dir);
+        if in_repo {
+            builder.add(dir.join(".git/info/exclude"));
+            builder.add(dir.join(".gitignore"));
+        }
+        builder.add(dir.join(".ignore"));
+        if let Ok(rules) = builder.build() {
+            if !rules.is_empty() {
+                self.rules.push(rules);
+            }
+        }
+    }
+
+    /// Returns whether the entry at `path`, whose absolute form is `absolute`, is left out
+    /// of the search by the globs, hidden-file rule, ignore files or file types.
+    ///
+    /// With `config.search_archives`, the globs and file types choose the members of
+    /// archives rather than the archives themselves, so they do not apply to files named
+    /// like archives.
+    fn is_skipped(&self, path: &Path, absolute: &Path, is_dir: bool) -> bool {
+        let filtered = is_dir || !self.config.search_archives || !has_archive_name(path);
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if let Some(included) = self.filters.glob(relative, is_dir).filter(|_| filtered) {
+            return !included;
+        }
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden && !self.config.hidden {
+            return true;
+        }
+        if !self.config.no_ignore && self.is_ignored(absolute, is_dir) {
+            return true;
+        }
+        filtered && self.filters.excludes_type(path, is_dir)
+    }
+
+    /// Returns whether the ignore rules exclude the entry at the absolute path `path`.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for rules in self.rules.iter().rev() {
+            match rules.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Returns whether `dir` is the root of a Git repository.
+fn is_repo_root(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a fresh directory under the system temp directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("quewuigrep-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn walks_sorted_and_skips_unwalked_directories() {
+        let root = temp_dir("walk");
+        fs::create_dir_all(root.join("b/nested")).unwrap();
+        fs::write(root.join("c.txt"), "c").unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b/nested/d.txt"), "d").unwrap();
+        let root_arg = [root.as_os_str().to_owned()];
+        let recursive = Config {
+            recursive: true,
+            ..Config::default()
+        };
+
+        let mut errors = Vec::new();
+        let filters = Filters::new(&recursive).unwrap();
+        let files = collect_files(&root_arg, &recursive, &filters, |err| errors.push(err));
+        assert_eq!(
+            files,
+            vec![
+                root.join("a.txt"),
+                root.join("b/nested/d.txt"),
+                root.join("c.txt")
+            ]
+        );
+        assert!(errors.is_empty());
+
+        let config = Config::default();
+        let files = collect_files(&root_arg, &config, &filters, |err| errors.push(err));
+        assert!(files.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], Error::IsDirectory { path } if *path == root));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn honours_ignore_files_and_hidden_entries() {
+        let root = temp_dir("ignore");
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        write(&root, ".git/info/exclude", "*.tmp\n");
+        write(
+            &root,
+            ".gitignore",
+            "target/\n*.log\n!keep.log\n/top.txt\ndocs/**/draft.md\n",
+        );
+        write(&root, ".ignore", "vendor\n");
+        write(&root, ".hidden/secret.rs", "");
+        write(&root, "target/debug/out.rs", "");
+        write(&root, "vendor/lib.rs", "");
+        write(&root, "a.tmp", "");
+        write(&root, "drop.log", "");
+        write(&root, "keep.log", "");
+        write(&root, "top.txt", "");
+        write(&root, "docs/guide/draft.md", "");
+        write(&root, "src/top.txt", "");
+        write(&root, "src/.gitignore", "!*.log\n");
+        write(&root, "src/debug.log", "");
+        write(&root, "src/main.rs", "");
+
+        let search = |config: Config, start: &Path| {
+            let config = Config {
+                recursive: true,
+                ..config
+            };
+            let start = [start.as_os_str().to_owned()];
+            let filters = Filters::new(&config).unwrap();
+            let files = collect_files(&start, &config, &filters, |err| panic!("{}", err));
+            files
+                .iter()
+                .map(|file| file.strip_prefix(&root).unwrap().display().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search(Config::default(), &root),
+            vec!["keep.log", "src/debug.log", "src/main.rs", "src/top.txt"]
+        );
+        // The rules of the directories above the one searched still apply.
+        fs::write(root.join("src/skip.tmp"), "").unwrap();
+        assert_eq!(
+            search(Config::default(), &root.join("src")),
+            vec!["src/debug.log", "src/main.rs", "src/top.txt"]
+        );
+        let hidden = search(
+            Config {
+                hidden: true,
+                ..Config::default()
+            },
+            &root,
+        );
+        assert!(hidden.contains(&".hidden/secret.rs".to_string()));
+        assert!(!hidden.contains(&"drop.log".to_string()));
+        let everything = search(
+            Config {
+                no_ignore: true,
+                ..Config::default()
+            },
+            &root,
+        );
+        assert_eq!(everything.len(), 11);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn filters_by_glob_and_type() {
+        let root = temp_dir("filter");
+        for file in [
+            "app.js",
+            "app.min.js",
+            "lib.rs",
+            "README.MD",
+            "notes.txt",
+            "web/index.html",
+        ] {
+            write(&root, file, "");
+        }
+        let search = |config: Config| {
+            let config = Config {
+                recursive: true,
+                ..config
+            };
+            let start = [root.as_os_str().to_owned()];
+            Filters::new(&config).map(|filters| {
+                collect_files(&start, &config, &filters, |err| panic!("{}", err))
+                    .iter()
+                    .map(|file| file.strip_prefix(&root).unwrap().display().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let config = Config::builder().glob("*.js").glob("!*.min.js").build();
+        assert_eq!(search(config).unwrap(), vec!["app.js"]);
+        let config = Config::builder().iglob("*.md").glob("*.txt").build();
+        assert_eq!(search(config).unwrap(), vec!["README.MD", "notes.txt"]);
+        let config = Config::builder().file_type("rust").build();
+        assert_eq!(search(config).unwrap(), vec!["lib.rs"]);
+        let config = Config::builder()
+            .file_type_not("js")
+            .file_type_not("web")
+            .type_add("web:*.html")
+            .build();
+        assert_eq!(
+            search(config).unwrap(),
+            vec!["README.MD", "lib.rs", "notes.txt"]
+        );
+        let config = Config::builder().file_type("nonsense").build();
+        assert!(matches!(search(config), Err(Error::Filter(_))));
+
+        // With archives searched, the filters choose their members instead.
+        write(&root, "vendor.zip", "");
+        let config = Config::builder().glob("*.js").glob("!*.min.js");
+        assert_eq!(search(config.clone().build()).unwrap(), vec!["app.js"]);
+        let config = config.search_archives(true).build();
+        assert_eq!(search(config).unwrap(), vec!["app.js", "vendor.zip"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}